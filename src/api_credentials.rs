@@ -0,0 +1,192 @@
+//! Credentials of Telegram API application, registered at
+//! https://my.telegram.org. They may be built into the binary by TG_ID and
+//! TG_HASH variables, or given at runtime.
+
+use crate::config::Profile;
+use crate::failure;
+use eyre::{eyre, Result};
+use std::env;
+
+// Variables used when credentials are not given by command line options.
+const ID_ENV: &str = "TG_TOOL_API_ID";
+const HASH_ENV: &str = "TG_TOOL_API_HASH";
+
+const COMPILED_ID: Option<i32> = match option_env!("TG_ID") {
+    Some(id) => match i32::from_str_radix(id, 10) {
+        Ok(v) => Some(v),
+        Err(_) => panic!("Invalid TG_ID environment variable"),
+    },
+    None => None,
+};
+const COMPILED_HASH: Option<&str> = option_env!("TG_HASH");
+
+// Values of ID_ENV and HASH_ENV variables, not parsed yet.
+#[derive(Default)]
+struct EnvCredentials {
+    id: Option<String>,
+    hash: Option<String>,
+}
+
+impl EnvCredentials {
+    fn read() -> Self {
+        Self {
+            id: env::var(ID_ENV).ok(),
+            hash: env::var(HASH_ENV).ok(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiCredentials {
+    pub id: i32,
+    pub hash: String,
+}
+
+impl ApiCredentials {
+    /// Returns credentials built into the binary, if any.
+    pub fn compiled() -> Option<Self> {
+        Self::from_compiled(COMPILED_ID, COMPILED_HASH)
+    }
+
+    fn from_compiled(id: Option<i32>, hash: Option<&str>) -> Option<Self> {
+        Some(Self {
+            id: id?,
+            hash: hash?.to_owned(),
+        })
+    }
+
+    /// Returns credentials given by |id| and |hash| command line options,
+    /// otherwise by environment variables, otherwise by |profile|. Each
+    /// source must give both of them.
+    pub fn explicit(
+        id: Option<i32>,
+        hash: Option<&str>,
+        profile: &Profile,
+    ) -> Result<Option<Self>> {
+        Self::from_sources(id, hash, EnvCredentials::read(), profile)
+    }
+
+    fn from_sources(
+        id: Option<i32>,
+        hash: Option<&str>,
+        env: EnvCredentials,
+        profile: &Profile,
+    ) -> Result<Option<Self>> {
+        let env_id = match env.id {
+            Some(id) => Some(id.trim().parse::<i32>().map_err(|e| {
+                failure::validation(eyre!("Invalid {ID_ENV} variable \"{id}\": {e}"))
+            })?),
+            None => None,
+        };
+        let env_hash = env.hash;
+        let sources = [
+            (
+                id,
+                hash.map(str::to_owned),
+                "--api-id and --api-hash options",
+            ),
+            (
+                env_id,
+                env_hash,
+                "TG_TOOL_API_ID and TG_TOOL_API_HASH variables",
+            ),
+            (
+                profile.api_id,
+                profile.api_hash.clone(),
+                "api_id and api_hash profile keys",
+            ),
+        ];
+        for source in sources {
+            match source {
+                (Some(id), Some(hash), _) => return Ok(Some(Self { id, hash })),
+                (None, None, _) => continue,
+                (_, _, names) => {
+                    return Err(failure::validation(eyre!("{names} must be given together")))
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Error for missing credentials, when they are not built into the binary.
+pub fn missing_error() -> eyre::Report {
+    failure::validation(eyre!(
+        "Telegram API ID and hash are required: register application at \
+         https://my.telegram.org and pass them by --api-id and --api-hash options, \
+         {ID_ENV} and {HASH_ENV} variables or api_id and api_hash profile keys"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> Profile {
+        Profile {
+            api_id: Some(1),
+            api_hash: Some("profile".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn credentials(id: i32, hash: &str) -> Option<ApiCredentials> {
+        Some(ApiCredentials {
+            id,
+            hash: hash.to_owned(),
+        })
+    }
+
+    #[test]
+    fn options_override_profile_and_come_in_pairs() {
+        let no_env = EnvCredentials::default;
+        let options =
+            ApiCredentials::from_sources(Some(2), Some("option"), no_env(), &profile()).unwrap();
+        assert_eq!(options, credentials(2, "option"));
+        assert!(ApiCredentials::from_sources(Some(2), None, no_env(), &profile()).is_err());
+        let from_profile = ApiCredentials::from_sources(None, None, no_env(), &profile()).unwrap();
+        assert_eq!(from_profile, credentials(1, "profile"));
+        let partial = Profile {
+            api_id: Some(1),
+            ..Default::default()
+        };
+        assert!(ApiCredentials::from_sources(None, None, no_env(), &partial).is_err());
+        assert_eq!(
+            ApiCredentials::from_sources(None, None, no_env(), &Profile::default()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn variables_override_profile() {
+        let env = || EnvCredentials {
+            id: Some(" 3 ".to_owned()),
+            hash: Some("env".to_owned()),
+        };
+        let from_env = ApiCredentials::from_sources(None, None, env(), &profile()).unwrap();
+        assert_eq!(from_env, credentials(3, "env"));
+        let options =
+            ApiCredentials::from_sources(Some(2), Some("option"), env(), &profile()).unwrap();
+        assert_eq!(options, credentials(2, "option"));
+        let invalid = EnvCredentials {
+            id: Some("abc".to_owned()),
+            hash: Some("env".to_owned()),
+        };
+        assert!(ApiCredentials::from_sources(None, None, invalid, &profile()).is_err());
+        let partial = EnvCredentials {
+            id: None,
+            hash: Some("env".to_owned()),
+        };
+        assert!(ApiCredentials::from_sources(None, None, partial, &profile()).is_err());
+    }
+
+    #[test]
+    fn compiled_requires_both_values() {
+        assert_eq!(
+            ApiCredentials::from_compiled(Some(4), Some("built")),
+            credentials(4, "built")
+        );
+        assert_eq!(ApiCredentials::from_compiled(Some(4), None), None);
+        assert_eq!(ApiCredentials::from_compiled(None, Some("built")), None);
+    }
+}
@@ -0,0 +1,465 @@
+use super::grammers::{request_name, Invoker};
+use super::TelegramBackend;
+use crate::utils;
+use eyre::{eyre, Result};
+use grammers_client::types::{Chat, User};
+use grammers_client::InvocationError;
+use grammers_tl_types as tl_types;
+use grammers_tl_types::{Deserializable, Serializable};
+use std::cell;
+use std::collections;
+
+/// In-memory backend for tests. Keeps dialog filters, so results of
+/// commands can be checked after running them.
+pub struct FakeBackend {
+    pub account_id: i64,
+    pub dialogs: Vec<Chat>,
+    // Chats which are not among dialogs, but can be found by username.
+    pub public_chats: Vec<Chat>,
+    pub abouts: collections::HashMap<i64, String>,
+    pub participants: collections::HashMap<i64, Vec<User>>,
+    // IDs of chats, which participants were requested.
+    pub participant_requests: cell::RefCell<Vec<i64>>,
+    // Chat IDs and queries of participant searches.
+    pub participant_searches: cell::RefCell<Vec<(i64, String)>>,
+    // Message texts of each chat, newest first.
+    pub messages: collections::HashMap<i64, Vec<String>>,
+    pub filters: cell::RefCell<Vec<tl_types::enums::DialogFilter>>,
+    // Updates of filters with these titles fail, like rejected by Telegram.
+    pub rejected_titles: Vec<String>,
+}
+
+impl FakeBackend {
+    pub fn new(account_id: i64, dialogs: Vec<Chat>) -> Self {
+        Self {
+            account_id,
+            dialogs,
+            public_chats: Vec::new(),
+            abouts: collections::HashMap::new(),
+            participants: collections::HashMap::new(),
+            participant_requests: cell::RefCell::new(Vec::new()),
+            participant_searches: cell::RefCell::new(Vec::new()),
+            messages: collections::HashMap::new(),
+            filters: cell::RefCell::new(Vec::new()),
+            rejected_titles: Vec::new(),
+        }
+    }
+
+    pub fn filter_titled(&self, title: &str) -> Option<tl_types::enums::DialogFilter> {
+        self.filters
+            .borrow()
+            .iter()
+            .find(|filter| filter_title(filter) == Some(title))
+            .cloned()
+    }
+}
+
+fn filter_title(filter: &tl_types::enums::DialogFilter) -> Option<&str> {
+    match filter {
+        tl_types::enums::DialogFilter::Filter(filter) => Some(&filter.title),
+        tl_types::enums::DialogFilter::Default => None,
+        tl_types::enums::DialogFilter::Chatlist(chat_list) => Some(&chat_list.title),
+    }
+}
+
+impl TelegramBackend for FakeBackend {
+    async fn get_me_id(&self) -> Result<i64> {
+        Ok(self.account_id)
+    }
+
+    async fn get_dialog_filters(&self) -> Result<tl_types::types::messages::DialogFilters> {
+        Ok(tl_types::types::messages::DialogFilters {
+            tags_enabled: false,
+            filters: self.filters.borrow().clone(),
+        })
+    }
+
+    async fn update_dialog_filter(
+        &self,
+        id: i32,
+        filter: Option<tl_types::enums::DialogFilter>,
+    ) -> Result<()> {
+        if let Some(title) = filter.as_ref().and_then(filter_title) {
+            if self.rejected_titles.iter().any(|t| t == title) {
+                return Err(eyre!("Filter {title} is rejected"));
+            }
+        }
+        // Like Telegram, uses |id| rather than ID inside of |filter|.
+        let filter = filter.map(|mut filter| {
+            utils::set_filter_id(&mut filter, id);
+            filter
+        });
+        let mut filters = self.filters.borrow_mut();
+        let position = filters
+            .iter()
+            .position(|f| utils::get_filter_id(f) == Some(id));
+        match (position, filter) {
+            (Some(idx), Some(filter)) => filters[idx] = filter,
+            (Some(idx), None) => {
+                filters.remove(idx);
+            }
+            (None, Some(filter)) => filters.push(filter),
+            (None, None) => return Err(eyre!("Filter {id} does not exist")),
+        }
+        Ok(())
+    }
+
+    async fn get_dialogs(&self) -> Result<Vec<Chat>> {
+        Ok(self.dialogs.clone())
+    }
+
+    async fn get_chat_about(&self, chat: &Chat) -> Result<Option<String>> {
+        if let Chat::User(_) = chat {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.abouts.get(&chat.id()).cloned().unwrap_or_default(),
+        ))
+    }
+
+    async fn get_participants(&self, chat: &Chat) -> Result<Vec<User>> {
+        self.participant_requests.borrow_mut().push(chat.id());
+        Ok(self
+            .participants
+            .get(&chat.id())
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn search_participants(&self, chat: &Chat, query: &str) -> Result<Vec<User>> {
+        self.participant_searches
+            .borrow_mut()
+            .push((chat.id(), query.to_owned()));
+        let query = query.to_lowercase();
+        let participants = self
+            .participants
+            .get(&chat.id())
+            .map_or(&[][..], Vec::as_slice);
+        Ok(participants
+            .iter()
+            .filter(|user| {
+                user.username()
+                    .into_iter()
+                    .chain([user.full_name().as_str()])
+                    .any(|name| name.to_lowercase().contains(&query))
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn get_recent_messages(&self, chat: &Chat, limit: usize) -> Result<Vec<String>> {
+        let messages = self.messages.get(&chat.id()).map_or(&[][..], Vec::as_slice);
+        Ok(messages.iter().take(limit).cloned().collect())
+    }
+
+    async fn resolve_username(&self, username: &str) -> Result<Option<Chat>> {
+        Ok(self
+            .dialogs
+            .iter()
+            .chain(&self.public_chats)
+            .find(|chat| {
+                chat.username()
+                    .into_iter()
+                    .chain(chat.usernames())
+                    .any(|u| u.eq_ignore_ascii_case(username))
+            })
+            .cloned())
+    }
+}
+
+pub fn user(id: i64, first_name: &str, username: Option<&str>) -> User {
+    User::from_raw(tl_types::enums::User::User(tl_types::types::User {
+        is_self: false,
+        contact: false,
+        mutual_contact: false,
+        deleted: false,
+        bot: false,
+        bot_chat_history: false,
+        bot_nochats: false,
+        verified: false,
+        restricted: false,
+        min: false,
+        bot_inline_geo: false,
+        support: false,
+        scam: false,
+        apply_min_photo: false,
+        fake: false,
+        bot_attach_menu: false,
+        premium: false,
+        attach_menu_enabled: false,
+        bot_can_edit: false,
+        close_friend: false,
+        stories_hidden: false,
+        stories_unavailable: false,
+        contact_require_premium: false,
+        bot_business: false,
+        bot_has_main_app: false,
+        id,
+        access_hash: Some(id * 10),
+        first_name: Some(first_name.to_owned()),
+        last_name: None,
+        username: username.map(|s| s.to_owned()),
+        phone: None,
+        photo: None,
+        status: None,
+        bot_info_version: None,
+        restriction_reason: None,
+        bot_inline_placeholder: None,
+        lang_code: None,
+        emoji_status: None,
+        usernames: None,
+        stories_max_id: None,
+        color: None,
+        profile_color: None,
+        bot_active_users: None,
+    }))
+}
+
+pub fn user_chat(id: i64, first_name: &str, username: Option<&str>) -> Chat {
+    Chat::User(user(id, first_name, username))
+}
+
+pub fn group_chat(id: i64, title: &str) -> Chat {
+    Chat::from_raw(tl_types::enums::Chat::Chat(tl_types::types::Chat {
+        creator: false,
+        left: false,
+        deactivated: false,
+        call_active: false,
+        call_not_empty: false,
+        noforwards: false,
+        id,
+        title: title.to_owned(),
+        photo: tl_types::enums::ChatPhoto::Empty,
+        participants_count: 0,
+        date: 0,
+        version: 0,
+        migrated_to: None,
+        admin_rights: None,
+        default_banned_rights: None,
+    }))
+}
+
+pub fn channel_chat(id: i64, title: &str, username: Option<&str>) -> Chat {
+    Chat::from_raw(tl_types::enums::Chat::Channel(tl_types::types::Channel {
+        creator: false,
+        left: false,
+        broadcast: true,
+        verified: false,
+        megagroup: false,
+        restricted: false,
+        signatures: false,
+        min: false,
+        scam: false,
+        has_link: false,
+        has_geo: false,
+        slowmode_enabled: false,
+        call_active: false,
+        call_not_empty: false,
+        fake: false,
+        gigagroup: false,
+        noforwards: false,
+        join_to_send: false,
+        join_request: false,
+        forum: false,
+        stories_hidden: false,
+        stories_hidden_min: false,
+        stories_unavailable: false,
+        signature_profiles: false,
+        id,
+        access_hash: Some(id * 10),
+        title: title.to_owned(),
+        username: username.map(|s| s.to_owned()),
+        photo: tl_types::enums::ChatPhoto::Empty,
+        date: 0,
+        restriction_reason: None,
+        admin_rights: None,
+        banned_rights: None,
+        default_banned_rights: None,
+        participants_count: None,
+        usernames: None,
+        stories_max_id: None,
+        color: None,
+        profile_color: None,
+        emoji_status: None,
+        level: None,
+        subscription_until_date: None,
+    }))
+}
+
+pub fn chatlist(id: i32, title: &str, peers: &[&Chat]) -> tl_types::enums::DialogFilter {
+    tl_types::enums::DialogFilter::Chatlist(tl_types::types::DialogFilterChatlist {
+        has_my_invites: false,
+        id,
+        title: title.to_owned(),
+        emoticon: None,
+        color: None,
+        pinned_peers: Vec::new(),
+        include_peers: peers
+            .iter()
+            .map(|chat| chat.pack().to_input_peer())
+            .collect(),
+    })
+}
+
+/// Returns IDs of peers included into |filter|.
+pub fn included_ids(filter: &tl_types::enums::DialogFilter) -> Vec<i64> {
+    let peers = match filter {
+        tl_types::enums::DialogFilter::Filter(filter) => &filter.include_peers,
+        tl_types::enums::DialogFilter::Default => return Vec::new(),
+        tl_types::enums::DialogFilter::Chatlist(chat_list) => &chat_list.include_peers,
+    };
+    let mut ids: Vec<i64> = peers
+        .iter()
+        .filter_map(|peer| match peer {
+            tl_types::enums::InputPeer::User(user) => Some(user.user_id),
+            tl_types::enums::InputPeer::Chat(chat) => Some(chat.chat_id),
+            tl_types::enums::InputPeer::Channel(channel) => Some(channel.channel_id),
+            _ => None,
+        })
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// Invoker for tests of GrammersBackend, answering requests with responses
+/// given in advance, in order.
+#[derive(Default)]
+pub struct ScriptedInvoker {
+    responses: cell::RefCell<collections::VecDeque<Result<Vec<u8>, InvocationError>>>,
+    // Names and contents of received requests.
+    pub requests: cell::RefCell<Vec<(&'static str, Vec<u8>)>>,
+}
+
+impl ScriptedInvoker {
+    pub fn respond(&self, response: &impl Serializable) {
+        self.responses
+            .borrow_mut()
+            .push_back(Ok(response.to_bytes()));
+    }
+
+    pub fn fail(&self, error: InvocationError) {
+        self.responses.borrow_mut().push_back(Err(error));
+    }
+}
+
+impl Invoker for ScriptedInvoker {
+    async fn invoke<R>(&self, request: &R) -> Result<R::Return>
+    where
+        R: tl_types::RemoteCall,
+        R::Return: Serializable + Clone + 'static,
+    {
+        let name = request_name::<R>();
+        self.requests.borrow_mut().push((name, request.to_bytes()));
+        match self.responses.borrow_mut().pop_front() {
+            None => Err(eyre!("Unexpected request {name}")),
+            Some(Ok(response)) => Ok(R::Return::from_bytes(&response)?),
+            Some(Err(e)) => Err(e.into()),
+        }
+    }
+}
+
+/// Returns Telegram error |name|, like ones in responses.
+pub fn rpc_error(code: i32, name: &str) -> InvocationError {
+    InvocationError::Rpc(grammers_mtsender::RpcError {
+        code,
+        name: name.to_owned(),
+        value: None,
+        caused_by: None,
+    })
+}
+
+/// Returns raw users and other chats out of |chats|, as they are given in
+/// responses.
+pub fn raw_chats(chats: &[&Chat]) -> (Vec<tl_types::enums::User>, Vec<tl_types::enums::Chat>) {
+    let mut users = Vec::new();
+    let mut raw_chats = Vec::new();
+    for chat in chats {
+        match chat {
+            Chat::User(user) => users.push(tl_types::enums::User::User(user.raw.clone())),
+            Chat::Group(group) => raw_chats.push(group.raw.clone()),
+            Chat::Channel(channel) => {
+                raw_chats.push(tl_types::enums::Chat::Channel(channel.raw.clone()))
+            }
+        }
+    }
+    (users, raw_chats)
+}
+
+pub fn message(chat: &Chat, id: i32, text: &str) -> tl_types::enums::Message {
+    tl_types::enums::Message::Message(tl_types::types::Message {
+        out: false,
+        mentioned: false,
+        media_unread: false,
+        silent: false,
+        post: false,
+        from_scheduled: false,
+        legacy: false,
+        edit_hide: false,
+        pinned: false,
+        noforwards: false,
+        invert_media: false,
+        offline: false,
+        id,
+        from_id: None,
+        from_boosts_applied: None,
+        peer_id: chat.pack().to_peer(),
+        saved_peer_id: None,
+        fwd_from: None,
+        via_bot_id: None,
+        via_business_bot_id: None,
+        reply_to: None,
+        // Older messages have lower IDs and dates.
+        date: id * 60,
+        message: text.to_owned(),
+        media: None,
+        reply_markup: None,
+        entities: None,
+        views: None,
+        forwards: None,
+        replies: None,
+        edit_date: None,
+        post_author: None,
+        grouped_id: None,
+        reactions: None,
+        restriction_reason: None,
+        ttl_period: None,
+        quick_reply_shortcut_id: None,
+        effect: None,
+        factcheck: None,
+    })
+}
+
+/// Returns dialog with |chat|, which latest message has |top_message| ID.
+pub fn dialog(chat: &Chat, top_message: i32) -> tl_types::enums::Dialog {
+    tl_types::enums::Dialog::Dialog(tl_types::types::Dialog {
+        pinned: false,
+        unread_mark: false,
+        view_forum_as_messages: false,
+        peer: chat.pack().to_peer(),
+        top_message,
+        read_inbox_max_id: top_message,
+        read_outbox_max_id: top_message,
+        unread_count: 0,
+        unread_mentions_count: 0,
+        unread_reactions_count: 0,
+        notify_settings: tl_types::enums::PeerNotifySettings::Settings(
+            tl_types::types::PeerNotifySettings {
+                show_previews: None,
+                silent: None,
+                mute_until: None,
+                ios_sound: None,
+                android_sound: None,
+                other_sound: None,
+                stories_muted: None,
+                stories_hide_sender: None,
+                stories_ios_sound: None,
+                stories_android_sound: None,
+                stories_other_sound: None,
+            },
+        ),
+        pts: None,
+        draft: None,
+        folder_id: None,
+        ttl_period: None,
+    })
+}
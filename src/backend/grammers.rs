@@ -0,0 +1,549 @@
+use super::TelegramBackend;
+use eyre::{eyre, Result};
+use grammers_client::types::{Chat, User};
+use grammers_client::{ChatMap, InvocationError};
+use grammers_tl_types as tl_types;
+use log::{debug, trace};
+use std::cell;
+use std::collections;
+use std::time;
+
+// Maximal numbers of items Telegram returns in one response.
+const DIALOGS_PAGE_SIZE: i32 = 100;
+const PARTICIPANTS_PAGE_SIZE: i32 = 200;
+const MESSAGES_PAGE_SIZE: usize = 100;
+
+/// Awaits |request| named |name|, logging its duration. Requests made by
+/// commands directly through grammers client, like login ones, are wrapped
+/// too, so all requests are timed alike.
+pub async fn timed<T, E>(
+    name: &str,
+    request: impl std::future::Future<Output = std::result::Result<T, E>>,
+) -> std::result::Result<T, E> {
+    let start = time::Instant::now();
+    let result = request.await;
+    let outcome = if result.is_ok() { "done" } else { "failed" };
+    debug!("Request {name} {outcome} in {:?}", start.elapsed());
+    result
+}
+
+/// Sends requests to Telegram. All requests of GrammersBackend go through
+/// it, so they can be recorded and replayed.
+#[allow(async_fn_in_trait)]
+pub trait Invoker {
+    async fn invoke<R>(&self, request: &R) -> Result<R::Return>
+    where
+        R: tl_types::RemoteCall,
+        R::Return: tl_types::Serializable + Clone + 'static;
+}
+
+impl Invoker for grammers_client::Client {
+    async fn invoke<R>(&self, request: &R) -> Result<R::Return>
+    where
+        R: tl_types::RemoteCall,
+        R::Return: tl_types::Serializable + Clone + 'static,
+    {
+        Ok(grammers_client::Client::invoke(self, request).await?)
+    }
+}
+
+/// Returns name of |R| request, e.g. "messages::GetDialogFilters" out of
+/// full type path.
+pub(super) fn request_name<R>() -> &'static str {
+    let full_name = std::any::type_name::<R>();
+    full_name
+        .rmatch_indices("::")
+        .nth(1)
+        .map_or(full_name, |(idx, _)| &full_name[idx + 2..])
+}
+
+/// Backend talking to Telegram servers through |invoker|, grammers client
+/// by default.
+pub struct GrammersBackend<I: Invoker = grammers_client::Client> {
+    invoker: I,
+    // Minimal interval between starts of requests.
+    request_interval: time::Duration,
+    last_request: cell::Cell<Option<time::Instant>>,
+}
+
+impl<I: Invoker> GrammersBackend<I> {
+    pub fn new(invoker: I) -> Self {
+        Self {
+            invoker,
+            request_interval: time::Duration::ZERO,
+            last_request: cell::Cell::new(None),
+        }
+    }
+
+    /// Makes requests to Telegram not more often than once per |interval|,
+    /// to stay away from flood limits on large accounts.
+    pub fn with_request_interval(mut self, interval: time::Duration) -> Self {
+        self.request_interval = interval;
+        self
+    }
+
+    // Waits until request interval passes since start of the last request.
+    async fn throttle(&self) {
+        if let Some(last_request) = self.last_request.get() {
+            let elapsed = last_request.elapsed();
+            if elapsed < self.request_interval {
+                tokio::time::sleep(self.request_interval - elapsed).await;
+            }
+        }
+    }
+
+    /// Sends |request| after waiting for request interval, logging its
+    /// duration.
+    async fn invoke<R>(&self, request: &R) -> Result<R::Return>
+    where
+        R: tl_types::RemoteCall + std::fmt::Debug,
+        R::Return: tl_types::Serializable + Clone + 'static,
+    {
+        let name = request_name::<R>();
+        trace!("Request {name}: {request:?}");
+        self.throttle().await;
+        self.last_request.set(Some(time::Instant::now()));
+        timed(name, self.invoker.invoke(request)).await
+    }
+
+    async fn get_chat_full(&self, chat: &Chat) -> Result<Option<tl_types::enums::ChatFull>> {
+        let tl_types::enums::messages::ChatFull::Full(full) = match chat {
+            Chat::User(_) => {
+                return Ok(None);
+            }
+            Chat::Group(group) => match &group.raw {
+                tl_types::enums::Chat::Empty(_)
+                | tl_types::enums::Chat::Forbidden(_)
+                | tl_types::enums::Chat::ChannelForbidden(_) => {
+                    return Ok(None);
+                }
+                tl_types::enums::Chat::Chat(chat) => {
+                    self.invoke(&tl_types::functions::messages::GetFullChat { chat_id: chat.id })
+                        .await?
+                }
+                tl_types::enums::Chat::Channel(channel) => {
+                    let inp_channel =
+                        tl_types::enums::InputChannel::Channel(tl_types::types::InputChannel {
+                            channel_id: channel.id,
+                            access_hash: channel.access_hash.unwrap(),
+                        });
+                    self.invoke(&tl_types::functions::channels::GetFullChannel {
+                        channel: inp_channel,
+                    })
+                    .await?
+                }
+            },
+            Chat::Channel(channel) => {
+                let inp_channel =
+                    tl_types::enums::InputChannel::Channel(tl_types::types::InputChannel {
+                        channel_id: channel.id(),
+                        access_hash: channel.raw.access_hash.unwrap(),
+                    });
+                self.invoke(&tl_types::functions::channels::GetFullChannel {
+                    channel: inp_channel,
+                })
+                .await?
+            }
+        };
+        Ok(Some(full.full_chat))
+    }
+
+    /// Returns participants of |chat| matching |filter|. Basic groups have
+    /// all participants in full chat information, so |filter| is ignored
+    /// for them.
+    async fn get_filtered_participants(
+        &self,
+        chat: &Chat,
+        filter: tl_types::enums::ChannelParticipantsFilter,
+    ) -> Result<Vec<User>> {
+        let packed = chat.pack();
+        if let Some(channel) = packed.try_to_input_channel() {
+            let mut request = tl_types::functions::channels::GetParticipants {
+                channel,
+                filter,
+                offset: 0,
+                limit: PARTICIPANTS_PAGE_SIZE,
+                hash: 0,
+            };
+            let mut users = Vec::new();
+            loop {
+                let tl_types::enums::channels::ChannelParticipants::Participants(page) =
+                    self.invoke(&request).await?
+                else {
+                    return Err(eyre!("Telegram returned participants as not modified"));
+                };
+                // Telegram may return less participants than asked for in
+                // the middle of the list, while all their users are given.
+                let last_page =
+                    usize::max(page.participants.len(), page.users.len()) < request.limit as usize;
+                request.offset += page.participants.len() as i32;
+                let ids = page.participants.iter().filter_map(channel_participant_id);
+                users.extend(participant_users(page.users, ids));
+                if last_page || page.participants.is_empty() {
+                    return Ok(users);
+                }
+            }
+        } else if let Some(chat_id) = packed.try_to_chat_id() {
+            let request = tl_types::functions::messages::GetFullChat { chat_id };
+            let tl_types::enums::messages::ChatFull::Full(full) = self.invoke(&request).await?;
+            let tl_types::enums::ChatFull::Full(chat_full) = full.full_chat else {
+                return Err(eyre!(
+                    "Telegram returned channel information for basic group"
+                ));
+            };
+            match chat_full.participants {
+                // Participants are hidden from the current account.
+                tl_types::enums::ChatParticipants::Forbidden(_) => Ok(Vec::new()),
+                tl_types::enums::ChatParticipants::Participants(participants) => {
+                    let ids = participants.participants.iter().map(|p| p.user_id());
+                    Ok(participant_users(full.users, ids))
+                }
+            }
+        } else {
+            // Dialogs with users have no participants.
+            Ok(Vec::new())
+        }
+    }
+}
+
+fn dialog_peer(dialog: &tl_types::enums::Dialog) -> &tl_types::enums::Peer {
+    match dialog {
+        tl_types::enums::Dialog::Dialog(dialog) => &dialog.peer,
+        tl_types::enums::Dialog::Folder(folder) => &folder.peer,
+    }
+}
+
+/// Returns peer, ID and date of |message|, None for empty messages.
+fn message_position(
+    message: &tl_types::enums::Message,
+) -> Option<(&tl_types::enums::Peer, i32, i32)> {
+    match message {
+        tl_types::enums::Message::Empty(_) => None,
+        tl_types::enums::Message::Message(message) => {
+            Some((&message.peer_id, message.id, message.date))
+        }
+        tl_types::enums::Message::Service(message) => {
+            Some((&message.peer_id, message.id, message.date))
+        }
+    }
+}
+
+fn channel_participant_id(participant: &tl_types::enums::ChannelParticipant) -> Option<i64> {
+    match participant {
+        tl_types::enums::ChannelParticipant::Participant(p) => Some(p.user_id),
+        tl_types::enums::ChannelParticipant::ParticipantSelf(p) => Some(p.user_id),
+        tl_types::enums::ChannelParticipant::Creator(p) => Some(p.user_id),
+        tl_types::enums::ChannelParticipant::Admin(p) => Some(p.user_id),
+        tl_types::enums::ChannelParticipant::Banned(
+            tl_types::types::ChannelParticipantBanned { peer, .. },
+        )
+        | tl_types::enums::ChannelParticipant::Left(tl_types::types::ChannelParticipantLeft {
+            peer,
+        }) => match peer {
+            tl_types::enums::Peer::User(user) => Some(user.user_id),
+            _ => None,
+        },
+    }
+}
+
+/// Returns users with |ids| out of |users| of response, which may also
+/// contain other users, like ones who invited participants.
+fn participant_users(
+    users: Vec<tl_types::enums::User>,
+    ids: impl Iterator<Item = i64>,
+) -> Vec<User> {
+    let mut users: collections::HashMap<i64, User> = users
+        .into_iter()
+        .map(|raw| {
+            let user = User::from_raw(raw);
+            (user.id(), user)
+        })
+        .collect();
+    ids.filter_map(|id| users.remove(&id)).collect()
+}
+
+impl<I: Invoker> TelegramBackend for GrammersBackend<I> {
+    async fn get_me_id(&self) -> Result<i64> {
+        let request = tl_types::functions::users::GetUsers {
+            id: vec![tl_types::enums::InputUser::UserSelf],
+        };
+        let me = self
+            .invoke(&request)
+            .await?
+            .pop()
+            .ok_or_else(|| eyre!("Telegram returned no current user"))?;
+        Ok(User::from_raw(me).id())
+    }
+
+    async fn get_dialog_filters(&self) -> Result<tl_types::types::messages::DialogFilters> {
+        let request = tl_types::functions::messages::GetDialogFilters {};
+        let tl_types::enums::messages::DialogFilters::Filters(filters) =
+            self.invoke(&request).await?;
+        Ok(filters)
+    }
+
+    async fn update_dialog_filter(
+        &self,
+        id: i32,
+        filter: Option<tl_types::enums::DialogFilter>,
+    ) -> Result<()> {
+        let request = tl_types::functions::messages::UpdateDialogFilter { id, filter };
+        self.invoke(&request).await?;
+        Ok(())
+    }
+
+    async fn get_dialogs(&self) -> Result<Vec<Chat>> {
+        let mut request = tl_types::functions::messages::GetDialogs {
+            exclude_pinned: false,
+            folder_id: None,
+            offset_date: 0,
+            offset_id: 0,
+            offset_peer: tl_types::enums::InputPeer::Empty,
+            limit: DIALOGS_PAGE_SIZE,
+            hash: 0,
+        };
+        let mut chats = Vec::new();
+        loop {
+            let (dialogs, messages, users, page_chats, last_page) =
+                match self.invoke(&request).await? {
+                    tl_types::enums::messages::Dialogs::Dialogs(d) => {
+                        (d.dialogs, d.messages, d.users, d.chats, true)
+                    }
+                    tl_types::enums::messages::Dialogs::Slice(d) => {
+                        let last_page = d.dialogs.len() < request.limit as usize;
+                        (d.dialogs, d.messages, d.users, d.chats, last_page)
+                    }
+                    tl_types::enums::messages::Dialogs::NotModified(_) => {
+                        return Err(eyre!("Telegram returned dialogs as not modified"));
+                    }
+                };
+            let chat_map = ChatMap::new(users, page_chats);
+            let page = dialogs
+                .iter()
+                .map(|dialog| {
+                    chat_map
+                        .get(dialog_peer(dialog))
+                        .cloned()
+                        .ok_or_else(|| eyre!("Telegram returned dialog with unknown peer"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let next_peer = page.last().map(|chat| chat.pack().to_input_peer());
+            chats.extend(page);
+            let Some(next_peer) = next_peer.filter(|_| !last_page) else {
+                return Ok(chats);
+            };
+            // Next page starts after top message of the last dialog having
+            // it, pinned dialogs were returned on the first page.
+            request.exclude_pinned = true;
+            let top_message = dialogs.iter().rev().find_map(|dialog| {
+                let tl_types::enums::Dialog::Dialog(dialog) = dialog else {
+                    return None;
+                };
+                messages
+                    .iter()
+                    .filter_map(message_position)
+                    .find(|(peer, id, _)| **peer == dialog.peer && *id == dialog.top_message)
+            });
+            if let Some((_, id, date)) = top_message {
+                request.offset_id = id;
+                request.offset_date = date;
+            }
+            request.offset_peer = next_peer;
+        }
+    }
+
+    async fn get_chat_about(&self, chat: &Chat) -> Result<Option<String>> {
+        let about = self
+            .get_chat_full(chat)
+            .await?
+            .map(|chat_full| match chat_full {
+                tl_types::enums::ChatFull::Full(full) => full.about,
+                tl_types::enums::ChatFull::ChannelFull(full) => full.about,
+            });
+        Ok(about)
+    }
+
+    async fn get_participants(&self, chat: &Chat) -> Result<Vec<User>> {
+        let filter = tl_types::enums::ChannelParticipantsFilter::ChannelParticipantsRecent;
+        self.get_filtered_participants(chat, filter).await
+    }
+
+    async fn search_participants(&self, chat: &Chat, query: &str) -> Result<Vec<User>> {
+        let filter = tl_types::enums::ChannelParticipantsFilter::ChannelParticipantsSearch(
+            tl_types::types::ChannelParticipantsSearch {
+                q: query.to_owned(),
+            },
+        );
+        self.get_filtered_participants(chat, filter).await
+    }
+
+    async fn get_recent_messages(&self, chat: &Chat, limit: usize) -> Result<Vec<String>> {
+        let mut request = tl_types::functions::messages::GetHistory {
+            peer: chat.pack().to_input_peer(),
+            offset_id: 0,
+            offset_date: 0,
+            add_offset: 0,
+            limit: 0,
+            max_id: 0,
+            min_id: 0,
+            hash: 0,
+        };
+        let mut texts = Vec::new();
+        while texts.len() < limit {
+            request.limit = (limit - texts.len()).min(MESSAGES_PAGE_SIZE) as i32;
+            let (messages, complete) = match self.invoke(&request).await? {
+                tl_types::enums::messages::Messages::Messages(m) => (m.messages, true),
+                tl_types::enums::messages::Messages::Slice(m) => (m.messages, false),
+                tl_types::enums::messages::Messages::ChannelMessages(m) => (m.messages, false),
+                tl_types::enums::messages::Messages::NotModified(_) => {
+                    return Err(eyre!("Telegram returned messages as not modified"));
+                }
+            };
+            let Some((_, id, date)) = messages.iter().rev().find_map(message_position) else {
+                break;
+            };
+            request.offset_id = id;
+            request.offset_date = date;
+            texts.extend(messages.into_iter().filter_map(|message| match message {
+                tl_types::enums::Message::Empty(_) => None,
+                tl_types::enums::Message::Message(message) => Some(message.message),
+                // Service messages, like joining of user, have no text.
+                tl_types::enums::Message::Service(_) => Some(String::new()),
+            }));
+            if complete {
+                break;
+            }
+        }
+        texts.truncate(limit);
+        Ok(texts)
+    }
+
+    async fn resolve_username(&self, username: &str) -> Result<Option<Chat>> {
+        let request = tl_types::functions::contacts::ResolveUsername {
+            username: username.to_owned(),
+        };
+        let resolved = match self.invoke(&request).await {
+            Ok(tl_types::enums::contacts::ResolvedPeer::Peer(resolved)) => resolved,
+            Err(e)
+                if e.downcast_ref::<InvocationError>()
+                    .is_some_and(|e| e.is("USERNAME_NOT_OCCUPIED")) =>
+            {
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        let chat_map = ChatMap::new(resolved.users, resolved.chats);
+        Ok(chat_map.get(&resolved.peer).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::{self, ScriptedInvoker};
+    use grammers_tl_types::Serializable;
+
+    #[tokio::test]
+    async fn dialogs_are_fetched_by_pages() {
+        let chats: Vec<Chat> = (1..=101)
+            .map(|id| fake::user_chat(id, "User", None))
+            .collect();
+        let page = |chats: &[Chat]| {
+            let (users, raw_chats) = fake::raw_chats(&chats.iter().collect::<Vec<_>>());
+            tl_types::enums::messages::Dialogs::Slice(tl_types::types::messages::DialogsSlice {
+                count: 101,
+                dialogs: chats
+                    .iter()
+                    .map(|chat| fake::dialog(chat, chat.id() as i32))
+                    .collect(),
+                messages: chats
+                    .iter()
+                    .map(|chat| fake::message(chat, chat.id() as i32, "Hi"))
+                    .collect(),
+                chats: raw_chats,
+                users,
+            })
+        };
+        let invoker = ScriptedInvoker::default();
+        invoker.respond(&page(&chats[..100]));
+        invoker.respond(&page(&chats[100..]));
+        let backend = GrammersBackend::new(invoker);
+        let dialogs = backend.get_dialogs().await.unwrap();
+        let ids: Vec<i64> = dialogs.iter().map(Chat::id).collect();
+        assert_eq!(ids, (1..=101).collect::<Vec<_>>());
+        let next_page = tl_types::functions::messages::GetDialogs {
+            exclude_pinned: true,
+            folder_id: None,
+            offset_date: 100 * 60,
+            offset_id: 100,
+            offset_peer: chats[99].pack().to_input_peer(),
+            limit: DIALOGS_PAGE_SIZE,
+            hash: 0,
+        };
+        let requests = backend.invoker.requests.borrow();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1], ("messages::GetDialogs", next_page.to_bytes()));
+    }
+
+    #[tokio::test]
+    async fn participants_are_fetched_by_pages() {
+        let channel = fake::channel_chat(100, "News", None);
+        let users: Vec<User> = (1..=201).map(|id| fake::user(id, "User", None)).collect();
+        let page = |participants: &[User], others: &[User]| {
+            tl_types::enums::channels::ChannelParticipants::Participants(
+                tl_types::types::channels::ChannelParticipants {
+                    count: 201,
+                    participants: participants
+                        .iter()
+                        .map(|user| {
+                            tl_types::enums::ChannelParticipant::Participant(
+                                tl_types::types::ChannelParticipant {
+                                    user_id: user.id(),
+                                    date: 0,
+                                    subscription_until_date: None,
+                                },
+                            )
+                        })
+                        .collect(),
+                    chats: Vec::new(),
+                    users: participants
+                        .iter()
+                        .chain(others)
+                        .map(|user| tl_types::enums::User::User(user.raw.clone()))
+                        .collect(),
+                },
+            )
+        };
+        let invoker = ScriptedInvoker::default();
+        invoker.respond(&page(&users[..200], &[]));
+        // Users, who invited participants, are given too.
+        invoker.respond(&page(&users[200..], &[fake::user(300, "Inviter", None)]));
+        let backend = GrammersBackend::new(invoker);
+        let participants = backend.get_participants(&channel).await.unwrap();
+        let ids: Vec<i64> = participants.iter().map(User::id).collect();
+        assert_eq!(ids, (1..=201).collect::<Vec<_>>());
+        let next_page = tl_types::functions::channels::GetParticipants {
+            channel: channel.pack().try_to_input_channel().unwrap(),
+            filter: tl_types::enums::ChannelParticipantsFilter::ChannelParticipantsRecent,
+            offset: 200,
+            limit: PARTICIPANTS_PAGE_SIZE,
+            hash: 0,
+        };
+        let requests = backend.invoker.requests.borrow();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[1],
+            ("channels::GetParticipants", next_page.to_bytes())
+        );
+    }
+
+    #[tokio::test]
+    async fn unoccupied_username_is_not_resolved() {
+        let invoker = ScriptedInvoker::default();
+        invoker.fail(fake::rpc_error(400, "USERNAME_NOT_OCCUPIED"));
+        invoker.fail(fake::rpc_error(420, "FLOOD_WAIT"));
+        let backend = GrammersBackend::new(invoker);
+        assert!(backend.resolve_username("nobody").await.unwrap().is_none());
+        assert!(backend.resolve_username("nobody").await.is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use eyre::{eyre, Result};
+use grammers_client::types::{Chat, User};
+use grammers_tl_types as tl_types;
+
+#[cfg(test)]
+pub mod fake;
+mod grammers;
+mod traffic;
+
+pub use self::grammers::{timed, GrammersBackend, Invoker};
+pub use traffic::{RecordingInvoker, ReplayInvoker};
+
+/// Telegram operations used by commands. Commands are written against this
+/// trait rather than grammers client, so they can be run against in-memory
+/// fake in tests.
+/// Returned futures are not required to be Send, so commands may be run
+/// only on current thread runtime.
+#[allow(async_fn_in_trait)]
+pub trait TelegramBackend {
+    /// Returns ID of the current account.
+    async fn get_me_id(&self) -> Result<i64>;
+    async fn get_dialog_filters(&self) -> Result<tl_types::types::messages::DialogFilters>;
+    /// Creates or replaces filter with |id|, or deletes it if |filter| is None.
+    async fn update_dialog_filter(
+        &self,
+        id: i32,
+        filter: Option<tl_types::enums::DialogFilter>,
+    ) -> Result<()>;
+    /// Returns chats of all account dialogs.
+    async fn get_dialogs(&self) -> Result<Vec<Chat>>;
+    /// Returns "about" description from full chat information, None for
+    /// chats without it (e.g. users).
+    async fn get_chat_about(&self, chat: &Chat) -> Result<Option<String>>;
+    async fn get_participants(&self, chat: &Chat) -> Result<Vec<User>>;
+    /// Returns participants of |chat| whose name or username contains
+    /// |query|, without fetching the whole list of large chats. Result may
+    /// include other participants, e.g. for basic groups, where search is
+    /// not available.
+    async fn search_participants(&self, chat: &Chat, query: &str) -> Result<Vec<User>>;
+    /// Returns texts of up to |limit| latest messages of |chat|, newest
+    /// first. Needed only by external classifiers, so backends may leave it
+    /// unsupported.
+    async fn get_recent_messages(&self, chat: &Chat, limit: usize) -> Result<Vec<String>> {
+        let _ = (chat, limit);
+        Err(eyre!("Reading messages is not supported by this backend"))
+    }
+    async fn resolve_username(&self, username: &str) -> Result<Option<Chat>>;
+}
+
+/// Backend chosen by command line options.
+pub enum AnyBackend {
+    Live(GrammersBackend),
+    Recording(GrammersBackend<RecordingInvoker<grammers_client::Client>>),
+    Replay(GrammersBackend<ReplayInvoker>),
+}
+
+macro_rules! dispatch {
+    ($self:ident, $backend:ident => $call:expr) => {
+        match $self {
+            AnyBackend::Live($backend) => $call,
+            AnyBackend::Recording($backend) => $call,
+            AnyBackend::Replay($backend) => $call,
+        }
+    };
+}
+
+impl TelegramBackend for AnyBackend {
+    async fn get_me_id(&self) -> Result<i64> {
+        dispatch!(self, b => b.get_me_id().await)
+    }
+
+    async fn get_dialog_filters(&self) -> Result<tl_types::types::messages::DialogFilters> {
+        dispatch!(self, b => b.get_dialog_filters().await)
+    }
+
+    async fn update_dialog_filter(
+        &self,
+        id: i32,
+        filter: Option<tl_types::enums::DialogFilter>,
+    ) -> Result<()> {
+        dispatch!(self, b => b.update_dialog_filter(id, filter).await)
+    }
+
+    async fn get_dialogs(&self) -> Result<Vec<Chat>> {
+        dispatch!(self, b => b.get_dialogs().await)
+    }
+
+    async fn get_chat_about(&self, chat: &Chat) -> Result<Option<String>> {
+        dispatch!(self, b => b.get_chat_about(chat).await)
+    }
+
+    async fn get_participants(&self, chat: &Chat) -> Result<Vec<User>> {
+        dispatch!(self, b => b.get_participants(chat).await)
+    }
+
+    async fn search_participants(&self, chat: &Chat, query: &str) -> Result<Vec<User>> {
+        dispatch!(self, b => b.search_participants(chat, query).await)
+    }
+
+    async fn get_recent_messages(&self, chat: &Chat, limit: usize) -> Result<Vec<String>> {
+        dispatch!(self, b => b.get_recent_messages(chat, limit).await)
+    }
+
+    async fn resolve_username(&self, username: &str) -> Result<Option<Chat>> {
+        dispatch!(self, b => b.resolve_username(username).await)
+    }
+}
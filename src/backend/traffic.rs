@@ -0,0 +1,335 @@
+use super::grammers::{request_name, Invoker};
+use eyre::{eyre, Result};
+use grammers_client::InvocationError;
+use grammers_tl_types as tl_types;
+use grammers_tl_types::{Deserializable, Serializable};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::cell;
+use std::collections;
+use std::fs;
+use std::path;
+
+const TRAFFIC_FORMAT_VERSION: u32 = 2;
+
+#[derive(Deserialize, Serialize)]
+struct RecordedRpcError {
+    code: i32,
+    name: String,
+    value: Option<u32>,
+}
+
+// Telegram objects are stored in TL binary form, encoded as hex, so they are
+// restored exactly as received.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Ok(String),
+    // Error returned by Telegram, e.g. FLOOD_WAIT.
+    RpcError(RecordedRpcError),
+    // Other errors, like network ones, with their messages.
+    Error(String),
+}
+
+#[derive(Deserialize, Serialize)]
+struct RecordedCall {
+    // Request name, like "messages::GetDialogs".
+    method: String,
+    // Request itself, identifying the call during replay.
+    request: String,
+    outcome: Outcome,
+}
+
+#[derive(Deserialize, Serialize)]
+struct TrafficFile {
+    format_version: u32,
+    calls: Vec<RecordedCall>,
+}
+
+// Recorded instead of message text with --redact.
+const REDACTED_MESSAGE: &str = "<redacted>";
+
+fn redact_users(users: &mut [tl_types::enums::User]) {
+    for user in users {
+        if let tl_types::enums::User::User(user) = user {
+            user.phone = None;
+        }
+    }
+}
+
+fn redact_messages(messages: &mut [tl_types::enums::Message]) {
+    for message in messages {
+        if let tl_types::enums::Message::Message(message) = message {
+            message.message = REDACTED_MESSAGE.to_owned();
+            message.entities = None;
+        }
+    }
+}
+
+/// Removes phone numbers and message texts from |response| of any request
+/// made by GrammersBackend.
+fn redact_response(response: &mut dyn Any) {
+    use tl_types::enums::{channels, contacts, messages};
+    if let Some(users) = response.downcast_mut::<Vec<tl_types::enums::User>>() {
+        redact_users(users);
+    } else if let Some(dialogs) = response.downcast_mut::<messages::Dialogs>() {
+        match dialogs {
+            messages::Dialogs::Dialogs(d) => {
+                redact_users(&mut d.users);
+                redact_messages(&mut d.messages);
+            }
+            messages::Dialogs::Slice(d) => {
+                redact_users(&mut d.users);
+                redact_messages(&mut d.messages);
+            }
+            messages::Dialogs::NotModified(_) => {}
+        }
+    } else if let Some(history) = response.downcast_mut::<messages::Messages>() {
+        match history {
+            messages::Messages::Messages(m) => {
+                redact_users(&mut m.users);
+                redact_messages(&mut m.messages);
+            }
+            messages::Messages::Slice(m) => {
+                redact_users(&mut m.users);
+                redact_messages(&mut m.messages);
+            }
+            messages::Messages::ChannelMessages(m) => {
+                redact_users(&mut m.users);
+                redact_messages(&mut m.messages);
+            }
+            messages::Messages::NotModified(_) => {}
+        }
+    } else if let Some(participants) = response.downcast_mut::<channels::ChannelParticipants>() {
+        if let channels::ChannelParticipants::Participants(p) = participants {
+            redact_users(&mut p.users);
+        }
+    } else if let Some(messages::ChatFull::Full(full)) =
+        response.downcast_mut::<messages::ChatFull>()
+    {
+        redact_users(&mut full.users);
+    } else if let Some(contacts::ResolvedPeer::Peer(resolved)) =
+        response.downcast_mut::<contacts::ResolvedPeer>()
+    {
+        redact_users(&mut resolved.users);
+    }
+}
+
+/// Invoker, recording all requests sent through the wrapped one with their
+/// responses. Recorded calls are written to the file when invoker is
+/// dropped, so failed runs are recorded as well.
+pub struct RecordingInvoker<I: Invoker> {
+    inner: I,
+    file_path: path::PathBuf,
+    // Remove phone numbers and message texts from recorded responses.
+    redact: bool,
+    calls: cell::RefCell<Vec<RecordedCall>>,
+}
+
+impl<I: Invoker> RecordingInvoker<I> {
+    pub fn new(inner: I, file_path: &path::Path, redact: bool) -> Self {
+        Self {
+            inner,
+            file_path: file_path.to_owned(),
+            redact,
+            calls: cell::RefCell::new(Vec::new()),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let file = TrafficFile {
+            format_version: TRAFFIC_FORMAT_VERSION,
+            calls: self.calls.take(),
+        };
+        fs::write(&self.file_path, serde_json::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+}
+
+impl<I: Invoker> Drop for RecordingInvoker<I> {
+    fn drop(&mut self) {
+        match self.save() {
+            Ok(()) => info!("Telegram traffic recorded to {}", self.file_path.display()),
+            Err(e) => error!(
+                "Failed write Telegram traffic to {}; {e}",
+                self.file_path.display()
+            ),
+        }
+    }
+}
+
+impl<I: Invoker> Invoker for RecordingInvoker<I> {
+    async fn invoke<R>(&self, request: &R) -> Result<R::Return>
+    where
+        R: tl_types::RemoteCall,
+        R::Return: Serializable + Clone + 'static,
+    {
+        let result = self.inner.invoke(request).await;
+        let outcome = match &result {
+            Ok(response) if self.redact => {
+                let mut response = response.clone();
+                redact_response(&mut response);
+                Outcome::Ok(hex::encode(response.to_bytes()))
+            }
+            Ok(response) => Outcome::Ok(hex::encode(response.to_bytes())),
+            Err(e) => match e.downcast_ref::<InvocationError>() {
+                Some(InvocationError::Rpc(rpc)) => Outcome::RpcError(RecordedRpcError {
+                    code: rpc.code,
+                    name: rpc.name.clone(),
+                    value: rpc.value,
+                }),
+                _ => Outcome::Error(e.to_string()),
+            },
+        };
+        self.calls.borrow_mut().push(RecordedCall {
+            method: request_name::<R>().to_owned(),
+            request: hex::encode(request.to_bytes()),
+            outcome,
+        });
+        result
+    }
+}
+
+/// Invoker, serving responses from file written by RecordingInvoker,
+/// without connecting to Telegram.
+pub struct ReplayInvoker {
+    // Calls with the same request are served in recorded order.
+    outcomes: cell::RefCell<collections::HashMap<(String, String), collections::VecDeque<Outcome>>>,
+}
+
+impl ReplayInvoker {
+    pub fn load(file_path: &path::Path) -> Result<Self> {
+        let content = fs::read_to_string(file_path)
+            .map_err(|e| eyre!("Failed open replay file {}; {e}", file_path.display()))?;
+        let file: TrafficFile = serde_json::from_str(&content)
+            .map_err(|e| eyre!("Failed parse replay file {}; {e}", file_path.display()))?;
+        if file.format_version != TRAFFIC_FORMAT_VERSION {
+            return Err(eyre!(
+                "Replay file format version {} is not supported",
+                file.format_version
+            ));
+        }
+        let mut outcomes = collections::HashMap::<_, collections::VecDeque<_>>::new();
+        for call in file.calls {
+            outcomes
+                .entry((call.method, call.request))
+                .or_default()
+                .push_back(call.outcome);
+        }
+        Ok(Self {
+            outcomes: cell::RefCell::new(outcomes),
+        })
+    }
+}
+
+impl Invoker for ReplayInvoker {
+    async fn invoke<R>(&self, request: &R) -> Result<R::Return>
+    where
+        R: tl_types::RemoteCall,
+        R::Return: Serializable + Clone + 'static,
+    {
+        let method = request_name::<R>();
+        let outcome = self
+            .outcomes
+            .borrow_mut()
+            .get_mut(&(method.to_owned(), hex::encode(request.to_bytes())))
+            .and_then(|queue| queue.pop_front());
+        match outcome {
+            None => Err(eyre!(
+                "Request {method} is absent in replay file, run differs from recorded one"
+            )),
+            Some(Outcome::Ok(encoded)) => Ok(R::Return::from_bytes(&hex::decode(encoded)?)?),
+            Some(Outcome::RpcError(rpc)) => {
+                Err(InvocationError::Rpc(grammers_mtsender::RpcError {
+                    code: rpc.code,
+                    name: rpc.name,
+                    value: rpc.value,
+                    caused_by: None,
+                })
+                .into())
+            }
+            // Kind of other errors is not recorded, they are treated as
+            // network ones.
+            Some(Outcome::Error(message)) => {
+                Err(eyre::Report::new(InvocationError::Dropped).wrap_err(message))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::{self, ScriptedInvoker};
+    use crate::backend::{GrammersBackend, TelegramBackend};
+
+    #[tokio::test]
+    async fn replay_serves_recorded_requests() {
+        let news = fake::channel_chat(100, "News", Some("news"));
+        let mut mom = fake::user(201, "Mom", Some("mom"));
+        mom.raw.phone = Some("123456".to_owned());
+        let mom = grammers_client::types::Chat::User(mom);
+        let (users, chats) = fake::raw_chats(&[&news, &mom]);
+        let invoker = ScriptedInvoker::default();
+        invoker.respond(&tl_types::enums::messages::Dialogs::Dialogs(
+            tl_types::types::messages::Dialogs {
+                dialogs: vec![fake::dialog(&news, 5), fake::dialog(&mom, 3)],
+                messages: vec![
+                    fake::message(&news, 5, "Breaking news"),
+                    fake::message(&mom, 3, "Call me"),
+                ],
+                chats,
+                users,
+            },
+        ));
+        invoker.respond(&tl_types::enums::messages::Messages::Messages(
+            tl_types::types::messages::Messages {
+                messages: vec![fake::message(&news, 5, "Breaking news")],
+                chats: Vec::new(),
+                users: Vec::new(),
+            },
+        ));
+        invoker.fail(fake::rpc_error(400, "USERNAME_NOT_OCCUPIED"));
+        invoker.fail(InvocationError::Dropped);
+        let file_path =
+            std::env::temp_dir().join(format!("tg-tool-traffic-{}.json", std::process::id()));
+
+        let recording = GrammersBackend::new(RecordingInvoker::new(invoker, &file_path, true));
+        recording.get_dialogs().await.unwrap();
+        // Only recorded responses are redacted.
+        let messages = recording.get_recent_messages(&news, 1).await.unwrap();
+        assert_eq!(messages, vec!["Breaking news"]);
+        assert!(recording
+            .resolve_username("nobody")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(recording.update_dialog_filter(9, None).await.is_err());
+        drop(recording);
+
+        let replay = GrammersBackend::new(ReplayInvoker::load(&file_path).unwrap());
+        fs::remove_file(&file_path).unwrap();
+        let dialogs = replay.get_dialogs().await.unwrap();
+        let titles: Vec<&str> = dialogs.iter().map(|chat| chat.name()).collect();
+        assert_eq!(titles, vec!["News", "Mom"]);
+        let grammers_client::types::Chat::User(mom) = &dialogs[1] else {
+            panic!("Dialog with user is expected");
+        };
+        assert_eq!(mom.username(), Some("mom"));
+        assert_eq!(mom.phone(), None);
+        let messages = replay.get_recent_messages(&dialogs[0], 1).await.unwrap();
+        assert_eq!(messages, vec![REDACTED_MESSAGE]);
+        assert!(replay.resolve_username("nobody").await.unwrap().is_none());
+        let error = replay.update_dialog_filter(9, None).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InvocationError>(),
+            Some(InvocationError::Dropped)
+        ));
+        let error = replay.get_dialogs().await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Request messages::GetDialogs is absent in replay file, run differs from recorded one"
+        );
+    }
+}
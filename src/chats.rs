@@ -0,0 +1,185 @@
+use crate::backend::TelegramBackend;
+use crate::dialogs::chat_flags;
+use eyre::{eyre, Result};
+use grammers_client::types::{Chat, User};
+use serde::Serialize;
+
+/// Chat found by username.
+#[derive(Debug, Serialize)]
+pub struct ChatInfo {
+    #[serde(rename = "type")]
+    pub chat_type: &'static str,
+    pub id: i64,
+    pub title: String,
+    pub username: Option<String>,
+    // Main username followed by collectible ones.
+    pub usernames: Vec<String>,
+    pub flags: Vec<&'static str>,
+}
+
+impl ChatInfo {
+    pub fn new(chat: &Chat) -> Self {
+        Self {
+            chat_type: match chat {
+                Chat::User(_) => "user",
+                Chat::Group(_) => "group",
+                Chat::Channel(_) => "channel",
+            },
+            id: chat.id(),
+            title: chat.name().to_owned(),
+            username: chat.username().map(str::to_owned),
+            usernames: chat
+                .username()
+                .into_iter()
+                .chain(chat.usernames())
+                .map(str::to_owned)
+                .collect(),
+            flags: chat_flags(chat),
+        }
+    }
+}
+
+/// Chat information, which needs additional request.
+#[derive(Debug, Serialize)]
+pub struct ChatDetails {
+    #[serde(flatten)]
+    pub chat: ChatInfo,
+    // None for users.
+    pub about: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParticipantInfo {
+    pub id: i64,
+    pub username: Option<String>,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    pub bot: bool,
+}
+
+impl ParticipantInfo {
+    fn new(user: &User) -> Self {
+        Self {
+            id: user.id(),
+            username: user.username().map(str::to_owned),
+            first_name: user.first_name().to_owned(),
+            last_name: user.last_name().map(str::to_owned),
+            bot: user.is_bot(),
+        }
+    }
+}
+
+/// Returns username out of |reference| to chat: username with or without
+/// "@", or t.me link. None if |reference| is not a valid username.
+pub fn parse_username(reference: &str) -> Option<&str> {
+    let link = reference
+        .strip_prefix("https://")
+        .or_else(|| reference.strip_prefix("http://"))
+        .unwrap_or(reference);
+    let username = match link
+        .strip_prefix("t.me/")
+        .or_else(|| link.strip_prefix("telegram.me/"))
+    {
+        // Links may point to message, like t.me/channel/123.
+        Some(path) => path.split(['/', '?']).next().unwrap_or_default(),
+        None => reference.strip_prefix('@').unwrap_or(reference),
+    };
+    let valid = (4..=32).contains(&username.len())
+        && username.starts_with(|c: char| c.is_ascii_alphabetic())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some(username)
+}
+
+/// Finds chat by |username|. Works for bot sessions too, unlike looking
+/// chats up among dialogs.
+pub async fn resolve_chat<B: TelegramBackend>(backend: &B, username: &str) -> Result<Chat> {
+    backend
+        .resolve_username(username)
+        .await?
+        .ok_or_else(|| eyre!("Chat @{username} is not found"))
+}
+
+pub async fn chat_details<B: TelegramBackend>(backend: &B, chat: &Chat) -> Result<ChatDetails> {
+    Ok(ChatDetails {
+        chat: ChatInfo::new(chat),
+        about: backend.get_chat_about(chat).await?,
+    })
+}
+
+/// Returns all participants of group or channel. Bots get participants of
+/// channels only where they are administrators.
+pub async fn export_participants<B: TelegramBackend>(
+    backend: &B,
+    chat: &Chat,
+) -> Result<Vec<ParticipantInfo>> {
+    if let Chat::User(_) = chat {
+        return Err(eyre!("{} is user, not group or channel", chat.name()));
+    }
+    let participants = backend.get_participants(chat).await?;
+    Ok(participants.iter().map(ParticipantInfo::new).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::{self, FakeBackend};
+
+    #[test]
+    fn usernames_are_parsed_from_references() {
+        assert_eq!(parse_username("@robonews"), Some("robonews"));
+        assert_eq!(parse_username("robo_news"), Some("robo_news"));
+        assert_eq!(
+            parse_username("https://t.me/robonews/123"),
+            Some("robonews")
+        );
+        assert_eq!(parse_username("t.me/robonews?start=1"), Some("robonews"));
+        assert_eq!(parse_username("telegram.me/robonews"), Some("robonews"));
+        assert_eq!(parse_username("12345"), None);
+        assert_eq!(parse_username("@bob"), None);
+        assert_eq!(parse_username("robo news"), None);
+        assert_eq!(parse_username("https://example.com/robonews"), None);
+    }
+
+    #[tokio::test]
+    async fn chat_is_resolved_and_described() {
+        let mut backend = FakeBackend::new(1, Vec::new());
+        backend.public_chats = vec![fake::channel_chat(100, "Robo news", Some("robonews"))];
+        backend.abouts.insert(100, "News for robots".to_owned());
+        let chat = resolve_chat(&backend, "RoboNews").await.unwrap();
+        let details = serde_json::to_value(chat_details(&backend, &chat).await.unwrap()).unwrap();
+        assert_eq!(
+            details,
+            serde_json::json!({
+                "type": "channel",
+                "id": 100,
+                "title": "Robo news",
+                "username": "robonews",
+                "usernames": ["robonews"],
+                "flags": [],
+                "about": "News for robots",
+            })
+        );
+        let error = resolve_chat(&backend, "nobody").await.unwrap_err();
+        assert_eq!(error.to_string(), "Chat @nobody is not found");
+    }
+
+    #[tokio::test]
+    async fn participants_are_exported() {
+        let family = fake::group_chat(300, "Family");
+        let mut backend = FakeBackend::new(1, vec![family.clone()]);
+        backend
+            .participants
+            .insert(300, vec![fake::user(201, "Mom", Some("mom"))]);
+        let participants = export_participants(&backend, &family).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(participants).unwrap(),
+            serde_json::json!([
+                {"id": 201, "username": "mom", "first_name": "Mom", "last_name": null, "bot": false}
+            ])
+        );
+        let bob = fake::user_chat(200, "Bob", Some("bob"));
+        assert!(export_participants(&backend, &bob).await.is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use super::login::{make_bot_capable_backend, BackendParams};
+use crate::failure;
+use eyre::{eyre, Result};
+use log::info;
+use tg_tool::backend::AnyBackend;
+use tg_tool::chats::{chat_details, export_participants, parse_username, resolve_chat, ChatInfo};
+
+async fn find_chat(
+    backend_params: &BackendParams,
+    chat: &str,
+) -> Result<(AnyBackend, grammers_client::types::Chat)> {
+    // Checked before connecting, so mistyped chat fails fast.
+    let username = parse_username(chat).ok_or_else(|| {
+        failure::validation(eyre!("Chat \"{chat}\" is neither username nor t.me link"))
+    })?;
+    let backend = make_bot_capable_backend(backend_params).await?;
+    let found = resolve_chat(&backend, username).await?;
+    Ok((backend, found))
+}
+
+pub async fn handle_chats_resolve_command(
+    backend_params: &BackendParams,
+    chat: &str,
+) -> Result<serde_json::Value> {
+    let (_, found) = find_chat(backend_params, chat).await?;
+    let info = ChatInfo::new(&found);
+    info!(
+        "{chat} is {} {} (id {})",
+        info.chat_type, info.title, info.id
+    );
+    Ok(serde_json::to_value(info)?)
+}
+
+pub async fn handle_chats_info_command(
+    backend_params: &BackendParams,
+    chat: &str,
+) -> Result<serde_json::Value> {
+    let (backend, found) = find_chat(backend_params, chat).await?;
+    let details = chat_details(&backend, &found).await?;
+    info!(
+        "{} {} (id {}, flags [{}]): {}",
+        details.chat.chat_type,
+        details.chat.title,
+        details.chat.id,
+        details.chat.flags.join(", "),
+        details.about.as_deref().unwrap_or("no description")
+    );
+    Ok(serde_json::to_value(details)?)
+}
+
+pub async fn handle_chats_participants_command(
+    backend_params: &BackendParams,
+    chat: &str,
+) -> Result<serde_json::Value> {
+    let (backend, found) = find_chat(backend_params, chat).await?;
+    let participants = export_participants(&backend, &found).await?;
+    for participant in &participants {
+        info!(
+            "{} {}{}{}",
+            participant.id,
+            participant.first_name,
+            participant
+                .last_name
+                .as_deref()
+                .map_or(String::new(), |name| format!(" {name}")),
+            participant
+                .username
+                .as_deref()
+                .map_or(String::new(), |username| format!(" @{username}")),
+        );
+    }
+    info!("{} has {} participants", found.name(), participants.len());
+    Ok(serde_json::json!({ "participants": participants }))
+}
@@ -1,55 +1,48 @@
-use super::login::make_client_from_session_file;
-use crate::serialization;
-use crate::utils;
+use super::login::{make_backend, BackendParams};
+use crate::failure;
 use eyre::Result;
-use grammers_tl_types as tl_types;
-
-use std::fs;
 use std::path;
+use tg_tool::folders::{backup_folders, clear_folders, restore_folders, validate_backup};
+use tg_tool::{formats, serialization};
 
 pub async fn handle_folders_backup_command(
-    session_file: &path::Path,
+    backend_params: &BackendParams,
     dst_file_path: &path::Path,
     pretty: bool,
-) -> Result<()> {
-    let tg_client = make_client_from_session_file(session_file).await?;
-    let filters = utils::get_dialog_filters(&tg_client).await?;
-    let f_out = fs::File::create(dst_file_path)?;
-    if pretty {
-        let mut ser = serde_json::Serializer::pretty(f_out);
-        serialization::DialogFiltersDef::serialize(&filters, &mut ser)?;
-    } else {
-        let mut ser = serde_json::Serializer::new(f_out);
-        serialization::DialogFiltersDef::serialize(&filters, &mut ser)?;
-    }
-    Ok(())
+    include_access_hash: bool,
+    format: Option<formats::FileFormat>,
+) -> Result<serde_json::Value> {
+    let format = formats::FileFormat::detect(format, dst_file_path);
+    let backend = make_backend(backend_params).await?;
+    let backup = backup_folders(&backend, include_access_hash).await?;
+    format.write(dst_file_path, &backup, pretty)?;
+    Ok(serde_json::json!({
+        "file": dst_file_path,
+        "folders_count": backup.folders.filters.len(),
+        "peers_count": backup.folders.peers().count(),
+    }))
 }
 
-pub async fn handle_folders_clear_command(session_file: &path::Path) -> Result<()> {
-    let tg_client = make_client_from_session_file(session_file).await?;
-    let filters = utils::get_dialog_filters(&tg_client).await?;
-    for filter in &filters.filters {
-        let maybe_filter_id = utils::get_filter_id(filter);
-        if let Some(filter_id) = maybe_filter_id {
-            let del_request = tl_types::functions::messages::UpdateDialogFilter {
-                id: filter_id,
-                filter: None,
-            };
-            tg_client.invoke(&del_request).await?;
-        }
-    }
-    Ok(())
+pub async fn handle_folders_clear_command(
+    backend_params: &BackendParams,
+) -> Result<serde_json::Value> {
+    let backend = make_backend(backend_params).await?;
+    let deleted = clear_folders(&backend).await?;
+    Ok(serde_json::json!({ "deleted": deleted }))
 }
 
 pub async fn handle_folders_restore_command(
-    session_file: &path::Path,
+    backend_params: &BackendParams,
     src_file_path: &path::Path,
-) -> Result<()> {
-    let f_in = fs::File::open(src_file_path)?;
-    let mut deserializer = serde_json::Deserializer::from_reader(f_in);
-    let saved_filters: tl_types::types::messages::DialogFilters =
-        serialization::DialogFiltersDef::deserialize(&mut deserializer)?;
-    let tg_client = make_client_from_session_file(session_file).await?;
-    utils::apply_dialog_filters(&tg_client, &saved_filters).await?;
-    Ok(())
+    format: Option<formats::FileFormat>,
+) -> Result<serde_json::Value> {
+    let format = formats::FileFormat::detect(format, src_file_path);
+    let backup = format
+        .read(src_file_path)
+        .and_then(serialization::BackupFile::from_value)
+        .and_then(|backup| validate_backup(&backup).map(|_| backup))
+        .map_err(failure::validation)?;
+    let backend = make_backend(backend_params).await?;
+    let report = restore_folders(&backend, &backup).await?;
+    failure::check_applied(&report.folders, serde_json::to_value(&report)?)
 }
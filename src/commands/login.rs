@@ -1,22 +1,59 @@
-use eyre::Result;
-use grammers_client::{session::Session, Client, Config, SignInError};
+use super::qr_login::qr_login;
+use crate::api_credentials::{self, ApiCredentials};
+use crate::failure::{self, Failure, FailureKind};
+use crate::proxy::ProxyUrl;
+use crate::session_file::{self, load_session, save_session, SessionKeyParams};
+use eyre::{eyre, Result};
+use grammers_client::client::auth::AuthorizationError;
+use grammers_client::types::PasswordToken;
+use grammers_client::{session::Session, Client, Config, InitParams, InvocationError, SignInError};
+use grammers_tl_types as tl_types;
+use log::{info, warn};
+use std::env;
 use std::fs;
 use std::io;
 use std::io::{BufRead, Write};
-#[cfg(unix)]
-use std::os::unix::fs::PermissionsExt;
 use std::path;
+use std::process;
+use std::time;
+use tg_tool::backend::{timed, AnyBackend, GrammersBackend, RecordingInvoker, ReplayInvoker};
 
-const API_ID: i32 = match i32::from_str_radix(env!("TG_ID"), 10) {
-    Ok(v) => v,
-    Err(_) => {
-        panic!("Invalid TG_ID environment variable")
-    }
-};
-const API_HASH: &str = env!("TG_HASH");
+// Environment variables with login credentials, used when they are not
+// given by command line options.
+const PHONE_ENV: &str = "TG_TOOL_PHONE";
+const CODE_ENV: &str = "TG_TOOL_CODE";
+const PASSWORD_ENV: &str = "TG_TOOL_PASSWORD";
+const BOT_TOKEN_ENV: &str = "TG_TOOL_BOT_TOKEN";
+// Attempts to enter login code or password, when they are asked again.
+const MAX_ATTEMPTS: usize = 3;
+
+/// Command line options of login command. Credentials not given by them
+/// are taken from environment variables, or asked interactively.
+pub struct LoginParams {
+    pub qr: bool,
+    pub phone: Option<String>,
+    // File with login code, written by some other process.
+    pub code_file: Option<path::PathBuf>,
+    // Shell command printing login code to stdout.
+    pub code_command: Option<String>,
+    pub password_file: Option<path::PathBuf>,
+    // Encrypt session file by passphrase.
+    pub encrypt: bool,
+    // API application given at runtime, instead of one built into the tool.
+    pub api: Option<ApiCredentials>,
+    // Overwrite session file holding logged in session.
+    pub force: bool,
+    pub proxy: Option<ProxyUrl>,
+    // Log in as bot with this token, given by @BotFather.
+    pub bot_token: Option<String>,
+    pub bot_token_file: Option<path::PathBuf>,
+}
 
+// Prompts and other messages for user go to stderr, since stdout is reserved
+// for JSON output.
 fn prompt(message: &str) -> Result<String> {
-    println!("{message}");
+    eprint!("{message}");
+    io::stderr().flush()?;
     let stdin = io::stdin();
     let mut stdin = stdin.lock();
     let mut line = String::new();
@@ -24,60 +61,436 @@ fn prompt(message: &str) -> Result<String> {
     Ok(line)
 }
 
-pub async fn handle_login_command(session_file: &path::Path) -> Result<()> {
-    let session = Session::new();
-    let client = Client::connect(Config {
+fn read_secret_file(file_path: &path::Path) -> Result<String> {
+    let content = fs::read_to_string(file_path)
+        .map_err(|e| failure::validation(eyre!("Failed to read {}: {e}", file_path.display())))?;
+    Ok(content.trim().to_owned())
+}
+
+/// Way to get login code or password.
+enum Secret<'a> {
+    // Given in advance, so it is useless to ask it again.
+    Fixed(String),
+    Command(&'a str),
+    Prompt,
+}
+
+impl Secret<'_> {
+    fn can_retry(&self) -> bool {
+        !matches!(self, Secret::Fixed(_))
+    }
+}
+
+async fn run_code_command(command: &str, phone: &str) -> Result<String> {
+    #[cfg(unix)]
+    let mut process = tokio::process::Command::new("sh");
+    #[cfg(unix)]
+    process.arg("-c");
+    #[cfg(windows)]
+    let mut process = tokio::process::Command::new("cmd");
+    #[cfg(windows)]
+    process.arg("/C");
+    let output = process
+        .arg(command)
+        .env(PHONE_ENV, phone)
+        .stdin(process::Stdio::null())
+        .stderr(process::Stdio::inherit())
+        .output()
+        .await
+        .map_err(|e| eyre!("Failed to run code command: {e}"))?;
+    if !output.status.success() {
+        return Err(eyre!("Code command failed with {}", output.status));
+    }
+    let code = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if code.is_empty() {
+        return Err(eyre!("Code command printed no code"));
+    }
+    Ok(code)
+}
+
+/// Converts error of Telegram request made during login to error with
+/// readable message and failure kind.
+fn login_error(error: InvocationError) -> eyre::Report {
+    let InvocationError::Rpc(rpc) = &error else {
+        return error.into();
+    };
+    let (kind, message) = match rpc.name.as_str() {
+        "FLOOD_WAIT" => (
+            FailureKind::Other,
+            format!(
+                "Too many login attempts, retry in {} seconds",
+                rpc.value.unwrap_or_default()
+            ),
+        ),
+        "PHONE_NUMBER_FLOOD" => (
+            FailureKind::Other,
+            "Too many login codes requested for this phone number, retry later".to_owned(),
+        ),
+        "PHONE_NUMBER_INVALID" => (
+            FailureKind::Validation,
+            "Phone number is invalid, use international format".to_owned(),
+        ),
+        "PHONE_NUMBER_BANNED" => (
+            FailureKind::Auth,
+            "Phone number is banned by Telegram".to_owned(),
+        ),
+        "PHONE_CODE_EXPIRED" => (
+            FailureKind::Auth,
+            "Login code expired, run login again".to_owned(),
+        ),
+        "ACCESS_TOKEN_INVALID" => (FailureKind::Auth, "Bot token is invalid".to_owned()),
+        "ACCESS_TOKEN_EXPIRED" => (FailureKind::Auth, "Bot token was revoked".to_owned()),
+        _ => return error.into(),
+    };
+    Failure::new(kind, format!("{message} ({error})")).into()
+}
+
+fn sign_in_error(error: SignInError) -> eyre::Report {
+    let message = match error {
+        SignInError::SignUpRequired { .. } => {
+            "Phone number is not registered in Telegram, sign up in Telegram app first"
+        }
+        SignInError::InvalidCode => "Invalid login code",
+        SignInError::InvalidPassword => "Invalid password",
+        SignInError::Other(e) => return login_error(e),
+        SignInError::PasswordRequired(_) => "Password is required",
+    };
+    Failure::new(FailureKind::Auth, message).into()
+}
+
+pub(super) async fn connect(
+    session: Session,
+    api: &ApiCredentials,
+    proxy: Option<&ProxyUrl>,
+) -> Result<Client> {
+    let config = Config {
         session,
-        api_id: API_ID,
-        api_hash: API_HASH.to_string(),
-        params: Default::default(),
-    })
+        api_id: api.id,
+        api_hash: api.hash.clone(),
+        params: InitParams {
+            proxy_url: proxy.map(|proxy| proxy.as_str().to_owned()),
+            ..Default::default()
+        },
+    };
+    let client = timed("connect", Client::connect(config)).await?;
+    Ok(client)
+}
+
+async fn is_authorized(client: &Client) -> Result<bool> {
+    Ok(timed("updates::GetState", client.is_authorized()).await?)
+}
+
+/// Requests parameters of two-step verification password. They are valid
+/// for one attempt to check password, so are requested again for retry.
+pub(super) async fn get_password_token(client: &Client) -> Result<PasswordToken> {
+    let tl_types::enums::account::Password::Password(password) = timed(
+        "account::GetPassword",
+        client.invoke(&tl_types::functions::account::GetPassword {}),
+    )
     .await?;
-    if !client.is_authorized().await? {
-        let phone = prompt("Enter your phone number (international format): ")?;
-        let token = client.request_login_code(&phone).await?;
-        let code = prompt("Enter the code you received: ")?;
-        let signed_in = client.sign_in(&token, &code).await;
-        match signed_in {
-            Err(SignInError::PasswordRequired(password_token)) => {
-                // Note: this `prompt` method will echo the password in the console.
-                //       Real code might want to use a better way to handle this.
+    Ok(PasswordToken::new(password))
+}
+
+/// Gets two-step verification password and finishes login with it.
+pub(super) async fn check_password(
+    client: &Client,
+    mut password_token: PasswordToken,
+    params: &LoginParams,
+) -> Result<()> {
+    let source = match (&params.password_file, env::var(PASSWORD_ENV)) {
+        (Some(password_file), _) => Secret::Fixed(read_secret_file(password_file)?),
+        (None, Ok(password)) => Secret::Fixed(password),
+        (None, Err(_)) => Secret::Prompt,
+    };
+    for attempt in 1..=MAX_ATTEMPTS {
+        let password = match &source {
+            Secret::Fixed(password) => password.clone(),
+            _ => {
                 let hint = password_token.hint().unwrap_or("None");
                 let prompt_message = format!("Enter the password (hint {}): ", &hint);
-                let password = rpassword::prompt_password(prompt_message).unwrap();
+                rpassword::prompt_password(prompt_message)?
+            }
+        };
+        let checked = timed(
+            "auth::CheckPassword",
+            client.check_password(password_token, password.trim()),
+        )
+        .await;
+        match checked {
+            Ok(_) => return Ok(()),
+            Err(SignInError::InvalidPassword) if source.can_retry() && attempt < MAX_ATTEMPTS => {
+                eprintln!("Invalid password, try again");
+                password_token = get_password_token(client).await?;
+            }
+            Err(e) => return Err(sign_in_error(e)),
+        }
+    }
+    unreachable!()
+}
 
-                client
-                    .check_password(password_token, password.trim())
-                    .await?;
+async fn phone_login(client: &Client, params: &LoginParams) -> Result<()> {
+    let phone = match (&params.phone, env::var(PHONE_ENV)) {
+        (Some(phone), _) => phone.clone(),
+        (None, Ok(phone)) => phone,
+        (None, Err(_)) => prompt("Enter your phone number (international format): ")?,
+    };
+    let phone = phone.trim();
+    let token = timed("auth::SendCode", client.request_login_code(phone))
+        .await
+        .map_err(|e| match e {
+            AuthorizationError::Invoke(e) => login_error(e),
+            e => e.into(),
+        })?;
+    let source = match (&params.code_file, &params.code_command, env::var(CODE_ENV)) {
+        (Some(code_file), _, _) => Secret::Fixed(read_secret_file(code_file)?),
+        (None, Some(code_command), _) => Secret::Command(code_command),
+        (None, None, Ok(code)) => Secret::Fixed(code),
+        (None, None, Err(_)) => Secret::Prompt,
+    };
+    for attempt in 1..=MAX_ATTEMPTS {
+        let code = match &source {
+            Secret::Fixed(code) => code.clone(),
+            Secret::Command(command) => run_code_command(command, phone).await?,
+            Secret::Prompt => prompt("Enter the code you received: ")?,
+        };
+        match timed("auth::SignIn", client.sign_in(&token, code.trim())).await {
+            Ok(_) => return Ok(()),
+            Err(SignInError::PasswordRequired(password_token)) => {
+                return check_password(client, password_token, params).await;
             }
-            Ok(_) => (),
-            Err(e) => panic!("{}", e),
+            Err(SignInError::InvalidCode) if source.can_retry() && attempt < MAX_ATTEMPTS => {
+                eprintln!("Invalid code, try again");
+            }
+            Err(e) => return Err(sign_in_error(e)),
         }
     }
-    let mut file = fs::File::create(session_file)?;
-    #[cfg(unix)]
-    file.set_permissions(fs::Permissions::from_mode(0o600))?;
-    file.set_len(0)?;
-    file.write_all(&client.session().save())?;
-    file.sync_data()?;
+    unreachable!()
+}
+
+/// Returns bot token given by option, file or environment variable. The
+/// variable is ignored when user login is requested by options.
+fn bot_token(params: &LoginParams) -> Result<Option<String>> {
+    if let Some(bot_token) = &params.bot_token {
+        return Ok(Some(bot_token.clone()));
+    }
+    if let Some(bot_token_file) = &params.bot_token_file {
+        return Ok(Some(read_secret_file(bot_token_file)?));
+    }
+    if params.qr || params.phone.is_some() {
+        return Ok(None);
+    }
+    Ok(env::var(BOT_TOKEN_ENV)
+        .ok()
+        .filter(|token| !token.trim().is_empty()))
+}
+
+async fn bot_login(client: &Client, token: &str) -> Result<()> {
+    let bot = timed(
+        "auth::ImportBotAuthorization",
+        client.bot_sign_in(token.trim()),
+    )
+    .await
+    .map_err(|e| match e {
+        AuthorizationError::Invoke(e) => login_error(e),
+        e => e.into(),
+    })?;
+    info!("Logged in as bot {}", bot.full_name());
     Ok(())
 }
 
-pub async fn make_client_from_session_file(session_file: &path::Path) -> Result<Client> {
-    let session = Session::load_file(session_file)?;
-    let client = Client::connect(Config {
-        session,
-        api_id: API_ID,
-        api_hash: API_HASH.to_string(),
-        params: Default::default(),
-    })
-    .await?;
+/// Fails for bot session of |client|, since |commands| are available only
+/// for user accounts.
+pub(super) fn require_user_account(client: &Client, commands: &str) -> Result<()> {
+    if client.session().get_user().is_some_and(|user| user.bot) {
+        return Err(failure::validation(eyre!(
+            "{commands} are not available for bot accounts"
+        )));
+    }
+    Ok(())
+}
+
+/// Logs in by phone number and code, by QR code, or as bot, according to
+/// |params|.
+pub async fn handle_login_command(
+    session_file: &path::Path,
+    params: &LoginParams,
+    key_params: &SessionKeyParams,
+) -> Result<serde_json::Value> {
+    if !params.force && session_file::is_signed_in(session_file) {
+        return Err(failure::validation(eyre!(
+            "Session file {} holds logged in session, run logout first or pass --force",
+            session_file.display()
+        )));
+    }
+    let api = params
+        .api
+        .clone()
+        .or_else(ApiCredentials::compiled)
+        .ok_or_else(api_credentials::missing_error)?;
+    let bot_token = bot_token(params)?;
+    let mut client = connect(Session::new(), &api, params.proxy.as_ref()).await?;
+    if let Some(bot_token) = &bot_token {
+        bot_login(&client, bot_token).await?;
+    } else if params.qr {
+        client = qr_login(client, &api, params).await?;
+    } else if !is_authorized(&client).await? {
+        phone_login(&client, params).await?;
+    }
+    save_session(
+        session_file,
+        client.session(),
+        &api,
+        // Configured key file means that session must never be written
+        // in plain text.
+        (params.encrypt || key_params.key_file.is_some()).then_some(key_params),
+    )?;
+    Ok(serde_json::json!({}))
+}
+
+/// Returns |explicit| API application, otherwise |stored| one the session
+/// was made with, otherwise one built into the tool.
+fn session_api(
+    explicit: Option<&ApiCredentials>,
+    stored: Option<ApiCredentials>,
+) -> Result<ApiCredentials> {
+    explicit
+        .cloned()
+        .or(stored)
+        .or_else(ApiCredentials::compiled)
+        .ok_or_else(api_credentials::missing_error)
+}
+
+/// Connects with session from |session_file|, according to |params|.
+pub async fn make_client_from_session_file(
+    session_file: &path::Path,
+    params: &BackendParams,
+) -> Result<Client> {
+    let stored = load_session(session_file, &params.session_key)?;
+    let api = session_api(params.api.as_ref(), stored.api)?;
+    let client = connect(stored.session, &api, params.proxy.as_ref()).await?;
+    if !is_authorized(&client).await? {
+        return Err(Failure::new(
+            FailureKind::Auth,
+            "Session is not authorized, run login command",
+        )
+        .into());
+    }
     Ok(client)
 }
 
-pub async fn handle_logout_command(session_file: &path::Path) -> Result<()> {
-    let client = make_client_from_session_file(session_file).await?;
-    client.sign_out().await?;
-    fs::remove_file(session_file)?;
-    Ok(())
+/// Command line options, defining how commands reach Telegram.
+pub struct BackendParams {
+    pub session_file: Option<path::PathBuf>,
+    // Source of passphrase for encrypted session file.
+    pub session_key: SessionKeyParams,
+    // API application given at runtime, overriding one stored in session.
+    pub api: Option<ApiCredentials>,
+    pub proxy: Option<ProxyUrl>,
+    // Record Telegram requests of backend to this file.
+    pub record_file: Option<path::PathBuf>,
+    // Remove phone numbers and message texts from recorded responses.
+    pub redact: bool,
+    // Serve Telegram requests from this file instead of connecting to Telegram.
+    pub replay_file: Option<path::PathBuf>,
+    // Minimal interval between requests to Telegram.
+    pub request_interval: Option<time::Duration>,
+}
+
+/// Makes client for commands working with account itself, which do not
+/// support recording and replaying.
+pub async fn make_client(params: &BackendParams) -> Result<Client> {
+    if params.record_file.is_some() || params.replay_file.is_some() {
+        return Err(failure::validation(eyre!(
+            "--record and --replay are supported only by folders, dialogs and chats commands"
+        )));
+    }
+    let session_file = params.session_file.as_deref().ok_or_else(|| {
+        failure::validation(eyre!("--session-file option is required for this command"))
+    })?;
+    make_client_from_session_file(session_file, params).await
+}
+
+/// Makes backend for commands working with account data, according to
+/// command line options.
+pub async fn make_backend(params: &BackendParams) -> Result<AnyBackend> {
+    // Telegram does not give bots list of their dialogs and folders.
+    make_backend_for(params, Some("Folders and dialogs commands")).await
+}
+
+/// Makes backend for commands, which work for bot sessions too.
+pub async fn make_bot_capable_backend(params: &BackendParams) -> Result<AnyBackend> {
+    make_backend_for(params, None).await
+}
+
+/// Makes backend according to command line options. Bot sessions are
+/// rejected, if |user_commands| naming the commands is given.
+async fn make_backend_for(
+    params: &BackendParams,
+    user_commands: Option<&str>,
+) -> Result<AnyBackend> {
+    if let Some(replay_file) = &params.replay_file {
+        let invoker = ReplayInvoker::load(replay_file)?;
+        return Ok(AnyBackend::Replay(GrammersBackend::new(invoker)));
+    }
+    let session_file = params.session_file.as_deref().ok_or_else(|| {
+        failure::validation(eyre!("--session-file option is required for this command"))
+    })?;
+    let client = make_client_from_session_file(session_file, params).await?;
+    if let Some(user_commands) = user_commands {
+        require_user_account(&client, user_commands)?;
+    }
+    let request_interval = params.request_interval.unwrap_or_default();
+    match &params.record_file {
+        Some(record_file) => {
+            let invoker = RecordingInvoker::new(client, record_file, params.redact);
+            Ok(AnyBackend::Recording(
+                GrammersBackend::new(invoker).with_request_interval(request_interval),
+            ))
+        }
+        None => Ok(AnyBackend::Live(
+            GrammersBackend::new(client).with_request_interval(request_interval),
+        )),
+    }
+}
+
+pub async fn handle_logout_command(
+    session_file: &path::Path,
+    params: &BackendParams,
+) -> Result<serde_json::Value> {
+    // Session file, which can not be read, is kept, since its session may
+    // be still active.
+    let stored = load_session(session_file, &params.session_key)?;
+    let api = session_api(params.api.as_ref(), stored.api)?;
+    let sign_out = async {
+        let client = connect(stored.session, &api, params.proxy.as_ref()).await?;
+        timed("auth::LogOut", client.sign_out()).await?;
+        Ok::<_, eyre::Report>(())
+    };
+    // Session may be already terminated, e.g. from other device, and
+    // stale file is useless anyway.
+    let signed_out = match sign_out.await {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Failed to terminate session on Telegram servers, it may be already terminated: {e}");
+            false
+        }
+    };
+    fs::remove_file(session_file)
+        .map_err(|e| eyre!("Failed to remove {}: {e}", session_file.display()))?;
+    Ok(serde_json::json!({ "signed_out": signed_out }))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn code_command_gets_phone_and_prints_code() {
+        let code = run_code_command("echo \" ${TG_TOOL_PHONE#+}42 \"", "+100")
+            .await
+            .unwrap();
+        assert_eq!(code, "10042");
+        assert!(run_code_command("true", "+100").await.is_err());
+        assert!(run_code_command("echo 42; exit 1", "+100").await.is_err());
+    }
 }
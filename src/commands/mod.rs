@@ -1,10 +1,23 @@
+mod chats;
 mod dialogs;
 mod folders;
 mod login;
+mod qr_login;
+mod session;
 
+pub use chats::handle_chats_info_command;
+pub use chats::handle_chats_participants_command;
+pub use chats::handle_chats_resolve_command;
 pub use dialogs::handle_dialogs_assign_command;
+pub use dialogs::handle_dialogs_check_rules_command;
+pub use dialogs::handle_dialogs_test_rules_command;
 pub use folders::handle_folders_backup_command;
 pub use folders::handle_folders_clear_command;
 pub use folders::handle_folders_restore_command;
 pub use login::handle_login_command;
 pub use login::handle_logout_command;
+pub use login::BackendParams;
+pub use login::LoginParams;
+pub use session::handle_session_list_command;
+pub use session::handle_session_terminate_command;
+pub use session::handle_session_whoami_command;
@@ -0,0 +1,138 @@
+use super::login::{check_password, connect, get_password_token, LoginParams};
+use crate::api_credentials::ApiCredentials;
+use crate::qr;
+use base64::Engine;
+use eyre::{eyre, Result};
+use grammers_client::session::Session;
+use grammers_client::types::User;
+use grammers_client::{Client, InvocationError};
+use grammers_tl_types as tl_types;
+use log::info;
+use std::time;
+use tg_tool::backend::timed;
+
+// How often Telegram is asked whether login token is accepted.
+const POLL_INTERVAL: time::Duration = time::Duration::from_secs(2);
+
+fn show_qr_code(token: &[u8]) -> Result<()> {
+    let url = format!(
+        "tg://login?token={}",
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(token)
+    );
+    // Stdout is reserved for JSON output.
+    eprintln!("{}", qr::render(url.as_bytes())?);
+    eprintln!("Scan this QR code in Telegram app: Settings > Devices > Link Desktop Device");
+    eprintln!("or open {url} on a logged in device");
+    Ok(())
+}
+
+/// Finishes login of |client| with |authorization| received for accepted
+/// login token.
+fn complete_login(
+    client: &Client,
+    authorization: tl_types::enums::auth::Authorization,
+) -> Result<()> {
+    let tl_types::enums::auth::Authorization::Authorization(authorization) = authorization else {
+        return Err(eyre!(
+            "Account is not registered, sign up in Telegram app first"
+        ));
+    };
+    let user = User::from_raw(authorization.user);
+    // Client has authorization key only for its home DC.
+    let dc_id = client
+        .session()
+        .get_dcs()
+        .into_iter()
+        .find(|dc| dc.auth.is_some())
+        .map(|dc| dc.id)
+        .ok_or_else(|| eyre!("Session has no authorization key"))?;
+    client.session().set_user(user.id(), dc_id, user.is_bot());
+    info!("Logged in as {}", user.full_name());
+    Ok(())
+}
+
+/// Asks for two-step verification password, required to finish login.
+async fn complete_login_with_password(client: &Client, params: &LoginParams) -> Result<()> {
+    let password_token = get_password_token(client).await?;
+    check_password(client, password_token, params).await
+}
+
+fn is_password_needed(error: &InvocationError) -> bool {
+    error.is("SESSION_PASSWORD_NEEDED")
+}
+
+/// Imports login token, accepted on other device, in DC |dc_id| where the
+/// account lives. Returns client connected to that DC.
+async fn import_login_token(
+    dc_id: i32,
+    token: Vec<u8>,
+    api: &ApiCredentials,
+    params: &LoginParams,
+) -> Result<Client> {
+    // Client connects to DC of stored user, so placeholder user makes it
+    // connect to |dc_id|. It is replaced once login is completed.
+    let session = Session::new();
+    session.set_user(0, dc_id, false);
+    let client = connect(session, api, params.proxy.as_ref()).await?;
+    let request = tl_types::functions::auth::ImportLoginToken { token };
+    match timed("auth::ImportLoginToken", client.invoke(&request)).await {
+        Ok(tl_types::enums::auth::LoginToken::Success(success)) => {
+            complete_login(&client, success.authorization)?
+        }
+        Ok(_) => return Err(eyre!("Login token was not accepted after DC migration")),
+        Err(e) if is_password_needed(&e) => complete_login_with_password(&client, params).await?,
+        Err(e) => return Err(e.into()),
+    }
+    Ok(client)
+}
+
+/// Logs in by QR code, scanned by Telegram app on already logged in
+/// device. Returns logged in client, which may differ from |client| if
+/// account lives in other DC.
+pub async fn qr_login(
+    client: Client,
+    api: &ApiCredentials,
+    params: &LoginParams,
+) -> Result<Client> {
+    let mut shown_expires = None;
+    loop {
+        let request = tl_types::functions::auth::ExportLoginToken {
+            api_id: api.id,
+            api_hash: api.hash.clone(),
+            except_ids: Vec::new(),
+        };
+        let token = match timed("auth::ExportLoginToken", client.invoke(&request)).await {
+            Ok(token) => token,
+            Err(e) if is_password_needed(&e) => {
+                complete_login_with_password(&client, params).await?;
+                return Ok(client);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        match token {
+            tl_types::enums::auth::LoginToken::Token(token) => {
+                let now = time::SystemTime::now()
+                    .duration_since(time::UNIX_EPOCH)?
+                    .as_secs() as i32;
+                // Each request returns new token, while shown one stays valid
+                // until it expires.
+                if shown_expires.is_none_or(|expires| expires <= now) {
+                    if shown_expires.is_some() {
+                        eprintln!("QR code expired, scan the new one");
+                    }
+                    show_qr_code(&token.token)?;
+                    shown_expires = Some(token.expires);
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+            tl_types::enums::auth::LoginToken::MigrateTo(migrate) => {
+                info!("Account lives in DC {}, reconnecting", migrate.dc_id);
+                return import_login_token(migrate.dc_id, migrate.token, api, params).await;
+            }
+            tl_types::enums::auth::LoginToken::Success(success) => {
+                complete_login(&client, success.authorization)?;
+                return Ok(client);
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use super::login::{make_client, require_user_account, BackendParams};
+use crate::failure::{Failure, FailureKind};
+use eyre::Result;
+use grammers_client::InvocationError;
+use grammers_tl_types as tl_types;
+use log::info;
+use tg_tool::backend::timed;
+
+fn format_date(timestamp: i32) -> String {
+    time::OffsetDateTime::from_unix_timestamp(timestamp.into())
+        .ok()
+        .and_then(|date| {
+            date.format(&time::format_description::well_known::Rfc3339)
+                .ok()
+        })
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+/// Converts error of request terminating sessions to error with readable
+/// message and failure kind.
+fn terminate_error(error: InvocationError) -> eyre::Report {
+    let (kind, message) = if error.is("FRESH_RESET_AUTHORISATION_FORBIDDEN") {
+        (
+            FailureKind::Auth,
+            "Sessions can be terminated only from session older than 24 hours",
+        )
+    } else if error.is("HASH_INVALID") {
+        (
+            FailureKind::Validation,
+            "Session with this hash is not found",
+        )
+    } else {
+        return error.into();
+    };
+    Failure::new(kind, format!("{message} ({error})")).into()
+}
+
+pub async fn handle_session_whoami_command(
+    backend_params: &BackendParams,
+) -> Result<serde_json::Value> {
+    let client = make_client(backend_params).await?;
+    let me = timed("users::GetUsers", client.get_me()).await?;
+    let dc = client.session().get_user().map(|user| user.dc);
+    info!(
+        "Logged in as {} (id {}, username {}, premium {}, DC {})",
+        me.full_name(),
+        me.id(),
+        me.username().unwrap_or("none"),
+        me.raw.premium,
+        dc.map_or("unknown".to_owned(), |dc| dc.to_string())
+    );
+    Ok(serde_json::json!({
+        "id": me.id(),
+        "first_name": me.first_name(),
+        "last_name": me.last_name(),
+        "username": me.username(),
+        "phone": me.phone(),
+        "premium": me.raw.premium,
+        "bot": me.is_bot(),
+        "dc": dc,
+    }))
+}
+
+pub async fn handle_session_list_command(
+    backend_params: &BackendParams,
+) -> Result<serde_json::Value> {
+    let client = make_client(backend_params).await?;
+    require_user_account(&client, "Session list and terminate commands")?;
+    let tl_types::enums::account::Authorizations::Authorizations(authorizations) = timed(
+        "account::GetAuthorizations",
+        client.invoke(&tl_types::functions::account::GetAuthorizations {}),
+    )
+    .await?;
+    let mut sessions = Vec::new();
+    for tl_types::enums::Authorization::Authorization(authorization) in
+        authorizations.authorizations
+    {
+        let date_active = format_date(authorization.date_active);
+        info!(
+            "{}{}: {} {} on {} {}, {} {}, {}, {} ({}), last active {date_active}",
+            authorization.hash,
+            if authorization.current {
+                " (current)"
+            } else {
+                ""
+            },
+            authorization.app_name,
+            authorization.app_version,
+            authorization.device_model,
+            authorization.platform,
+            authorization.system_version,
+            authorization.api_id,
+            authorization.ip,
+            authorization.region,
+            authorization.country,
+        );
+        sessions.push(serde_json::json!({
+            "hash": authorization.hash,
+            "current": authorization.current,
+            "official_app": authorization.official_app,
+            "password_pending": authorization.password_pending,
+            "app_name": authorization.app_name,
+            "app_version": authorization.app_version,
+            "api_id": authorization.api_id,
+            "device_model": authorization.device_model,
+            "platform": authorization.platform,
+            "system_version": authorization.system_version,
+            "ip": authorization.ip,
+            "country": authorization.country,
+            "region": authorization.region,
+            "date_created": format_date(authorization.date_created),
+            "date_active": date_active,
+        }));
+    }
+    Ok(serde_json::json!({ "sessions": sessions }))
+}
+
+/// Terminates session with |hash|, shown by "session list", or all sessions
+/// except the current one if |hash| is None.
+pub async fn handle_session_terminate_command(
+    backend_params: &BackendParams,
+    hash: Option<i64>,
+) -> Result<serde_json::Value> {
+    let client = make_client(backend_params).await?;
+    require_user_account(&client, "Session list and terminate commands")?;
+    match hash {
+        Some(hash) => {
+            let request = tl_types::functions::account::ResetAuthorization { hash };
+            timed("account::ResetAuthorization", client.invoke(&request))
+                .await
+                .map_err(terminate_error)?;
+            info!("Terminated session {hash}");
+        }
+        None => {
+            let request = tl_types::functions::auth::ResetAuthorizations {};
+            timed("auth::ResetAuthorizations", client.invoke(&request))
+                .await
+                .map_err(terminate_error)?;
+            info!("Terminated all other sessions");
+        }
+    }
+    Ok(serde_json::json!({ "hash": hash, "all_others": hash.is_none() }))
+}
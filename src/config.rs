@@ -0,0 +1,305 @@
+use crate::proxy::ProxyUrl;
+use eyre::{eyre, Result};
+use log::LevelFilter;
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+use std::collections;
+use std::env;
+use std::fs;
+use std::path;
+use std::time;
+
+/// Settings of one account.
+#[serde_as]
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub session_file: Option<path::PathBuf>,
+    // File with passphrase of encrypted session file.
+    pub session_key_file: Option<path::PathBuf>,
+    // Used by commands taking rules file, when it is not given explicitly.
+    pub rules_file: Option<path::PathBuf>,
+    // Directory for backups made without explicit file path.
+    pub backup_dir: Option<path::PathBuf>,
+    // Log level of the tool, adjusted by -v and -q.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub log_level: Option<LevelFilter>,
+    // Minimal interval between requests to Telegram, in milliseconds.
+    pub request_interval_ms: Option<u64>,
+    // Telegram API application, used instead of one built into the tool.
+    pub api_id: Option<i32>,
+    pub api_hash: Option<String>,
+    // SOCKS5 proxy used to connect to Telegram.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub proxy: Option<ProxyUrl>,
+}
+
+impl Profile {
+    pub fn request_interval(&self) -> Option<time::Duration> {
+        self.request_interval_ms.map(time::Duration::from_millis)
+    }
+}
+
+/// Configuration file, holding named profiles.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // Profile used when --profile is not given.
+    pub default_profile: Option<String>,
+    // Profiles used with --all-accounts.
+    #[serde(default)]
+    pub accounts: Vec<String>,
+    #[serde(default)]
+    pub profiles: collections::BTreeMap<String, Profile>,
+}
+
+/// Returns path of configuration file used when --config is not given:
+/// tg-tool/config.toml in $XDG_CONFIG_HOME or in ~/.config.
+pub fn default_config_path() -> Option<path::PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => path::PathBuf::from(dir),
+        _ => path::PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("tg-tool").join("config.toml"))
+}
+
+/// Resolves |file_path| from configuration file in directory |base_dir|.
+fn expand_path(base_dir: &path::Path, file_path: &mut Option<path::PathBuf>) {
+    if let Some(file_path) = file_path {
+        let expanded = shellexpand::tilde(&file_path.to_string_lossy()).into_owned();
+        *file_path = base_dir.join(expanded);
+    }
+}
+
+/// Loads configuration from |explicit_path|, or from default location if it
+/// is not given. Missing file at default location means empty configuration.
+pub fn load_config(explicit_path: Option<&path::Path>) -> Result<Config> {
+    let config_path = match explicit_path {
+        Some(config_path) => config_path.to_owned(),
+        None => match default_config_path() {
+            Some(config_path) if config_path.exists() => config_path,
+            _ => return Ok(Config::default()),
+        },
+    };
+    let content = fs::read_to_string(&config_path)
+        .map_err(|e| eyre!("Failed to read config {}: {e}", config_path.display()))?;
+    let mut config: Config = toml::from_str(&content)
+        .map_err(|e| eyre!("Failed to parse config {}: {e}", config_path.display()))?;
+    // Relative paths are relative to directory of configuration file.
+    let base_dir = config_path.parent().unwrap_or(path::Path::new(""));
+    for profile in config.profiles.values_mut() {
+        expand_path(base_dir, &mut profile.session_file);
+        expand_path(base_dir, &mut profile.session_key_file);
+        expand_path(base_dir, &mut profile.rules_file);
+        expand_path(base_dir, &mut profile.backup_dir);
+    }
+    Ok(config)
+}
+
+impl Config {
+    /// Returns profile named |name|, or default one if |name| is None.
+    /// Returns empty profile if neither is given.
+    pub fn profile(&self, name: Option<&str>) -> Result<&Profile> {
+        static EMPTY: Profile = Profile {
+            session_file: None,
+            session_key_file: None,
+            rules_file: None,
+            backup_dir: None,
+            log_level: None,
+            request_interval_ms: None,
+            api_id: None,
+            api_hash: None,
+            proxy: None,
+        };
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(&EMPTY);
+        };
+        self.profiles
+            .get(name)
+            .ok_or_else(|| eyre!("Profile \"{name}\" is not found in config"))
+    }
+
+    /// Returns accounts to run command for. |profiles| and |session_files|
+    /// are given on command line, |all_accounts| selects profiles listed in
+    /// "accounts". Each of several session files makes separate account with
+    /// settings of the only selected profile.
+    pub fn accounts(
+        &self,
+        profiles: &[String],
+        all_accounts: bool,
+        session_files: &[path::PathBuf],
+    ) -> Result<Vec<Account>> {
+        let profile_names: Vec<Option<&str>> = if all_accounts {
+            if self.accounts.is_empty() {
+                return Err(eyre!("--all-accounts requires \"accounts\" list in config"));
+            }
+            self.accounts
+                .iter()
+                .map(|name| Some(name.as_str()))
+                .collect()
+        } else if profiles.is_empty() {
+            vec![None]
+        } else {
+            profiles.iter().map(|name| Some(name.as_str())).collect()
+        };
+        let mut accounts = Vec::new();
+        for name in profile_names {
+            accounts.push(Account {
+                name: name
+                    .or(self.default_profile.as_deref())
+                    .unwrap_or("default")
+                    .to_owned(),
+                profile: self.profile(name)?.clone(),
+            });
+        }
+        match session_files {
+            [] => Ok(accounts),
+            [session_file] if accounts.len() == 1 => {
+                accounts[0].profile.session_file = Some(session_file.clone());
+                Ok(accounts)
+            }
+            _ if accounts.len() == 1 => Ok(session_files
+                .iter()
+                .map(|session_file| {
+                    let mut profile = accounts[0].profile.clone();
+                    profile.session_file = Some(session_file.clone());
+                    Account {
+                        name: session_file.display().to_string(),
+                        profile,
+                    }
+                })
+                .collect()),
+            _ => Err(eyre!(
+                "--session-file can not be combined with several profiles"
+            )),
+        }
+    }
+}
+
+/// Account to run command for.
+pub struct Account {
+    // Profile name, or session file path if it is given on command line.
+    pub name: String,
+    pub profile: Profile,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_paths_are_relative_to_config() {
+        let dir = env::temp_dir().join(format!("tg-tool-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("config.toml");
+        fs::write(
+            &config_path,
+            r#"
+default_profile = "work"
+[profiles.work]
+session_file = "work.session"
+backup_dir = "/var/backups/tg"
+log_level = "warn"
+request_interval_ms = 500
+proxy = "socks5://localhost:1080"
+[profiles.personal]
+"#,
+        )
+        .unwrap();
+        let config = load_config(Some(&config_path)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let work = config.profile(None).unwrap();
+        assert_eq!(work.session_file, Some(dir.join("work.session")));
+        assert_eq!(work.backup_dir, Some("/var/backups/tg".into()));
+        assert_eq!(work.log_level, Some(LevelFilter::Warn));
+        assert_eq!(
+            work.proxy.as_ref().map(ProxyUrl::as_str),
+            Some("socks5://localhost:1080")
+        );
+        assert_eq!(
+            work.request_interval(),
+            Some(time::Duration::from_millis(500))
+        );
+        assert!(config
+            .profile(Some("personal"))
+            .unwrap()
+            .session_file
+            .is_none());
+        assert!(config.profile(Some("other")).is_err());
+        assert!(config.accounts(&[], true, &[]).is_err());
+        let session_files = ["a.session".into(), "b.session".into()];
+        let accounts = config.accounts(&[], false, &session_files).unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[1].name, "b.session");
+        assert_eq!(accounts[1].profile.log_level, Some(LevelFilter::Warn));
+        let profiles = ["work".to_owned(), "personal".to_owned()];
+        assert!(config.accounts(&profiles, false, &session_files).is_err());
+    }
+
+    #[test]
+    fn accounts_combine_profiles_and_session_files() {
+        let config: Config = toml::from_str(
+            r#"
+default_profile = "work"
+accounts = ["work", "personal"]
+[profiles.work]
+session_file = "work.session"
+log_level = "warn"
+[profiles.personal]
+session_file = "personal.session"
+"#,
+        )
+        .unwrap();
+        let names = |accounts: &[Account]| -> Vec<String> {
+            accounts.iter().map(|a| a.name.clone()).collect()
+        };
+        let session_file = |account: &Account| account.profile.session_file.clone().unwrap();
+        let work = ["work".to_owned()];
+        let both = ["work".to_owned(), "personal".to_owned()];
+        let one_file = ["my.session".into()];
+        let two_files = ["a.session".into(), "b.session".into()];
+
+        let accounts = config.accounts(&[], false, &[]).unwrap();
+        assert_eq!(names(&accounts), vec!["work"]);
+        assert_eq!(session_file(&accounts[0]), path::Path::new("work.session"));
+        // Single session file overrides one of the profile.
+        let accounts = config.accounts(&[], false, &one_file).unwrap();
+        assert_eq!(names(&accounts), vec!["work"]);
+        assert_eq!(session_file(&accounts[0]), path::Path::new("my.session"));
+        let accounts = config.accounts(&both, false, &[]).unwrap();
+        assert_eq!(names(&accounts), vec!["work", "personal"]);
+        assert_eq!(
+            session_file(&accounts[1]),
+            path::Path::new("personal.session")
+        );
+        // Several session files make accounts with settings of the profile.
+        let accounts = config.accounts(&work, false, &two_files).unwrap();
+        assert_eq!(names(&accounts), vec!["a.session", "b.session"]);
+        assert_eq!(accounts[1].profile.log_level, Some(LevelFilter::Warn));
+        for session_files in [&one_file[..], &two_files[..]] {
+            let error = config.accounts(&both, false, session_files).err().unwrap();
+            assert_eq!(
+                error.to_string(),
+                "--session-file can not be combined with several profiles"
+            );
+            assert!(config.accounts(&[], true, session_files).is_err());
+        }
+        let accounts = config.accounts(&[], true, &[]).unwrap();
+        assert_eq!(names(&accounts), vec!["work", "personal"]);
+        let unknown = ["other".to_owned()];
+        assert!(config.accounts(&unknown, false, &[]).is_err());
+
+        let no_accounts = Config {
+            accounts: Vec::new(),
+            ..config
+        };
+        let error = no_accounts.accounts(&[], true, &[]).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "--all-accounts requires \"accounts\" list in config"
+        );
+        assert!(no_accounts.accounts(&[], true, &one_file).is_err());
+    }
+}
@@ -0,0 +1,521 @@
+use crate::backend::TelegramBackend;
+use crate::rules::{apply_rules, ChatFilters, DialogProperties, DialogType};
+use crate::utils;
+use eyre::Result;
+use grammers_tl_types as tl_types;
+use log::{debug, info};
+use serde::Serialize;
+use std::cell;
+use std::collections;
+
+#[derive(Debug, Serialize)]
+pub struct AssignReport {
+    pub dialogs_count: usize,
+    // Titles of dialogs, assigned to each folder.
+    pub assigned: collections::BTreeMap<String, Vec<String>>,
+    pub folders: utils::ApplyReport,
+}
+
+struct DialogInfo<'a, B: TelegramBackend> {
+    chat: grammers_client::types::Chat,
+    backend: &'a B,
+    about: cell::OnceCell<Option<String>>,
+    // Usernames of participants, fetched only when full list is needed.
+    participants: cell::OnceCell<Vec<String>>,
+    // Whether participant with login is present, for logins looked up
+    // without fetching full list.
+    found_participants: cell::RefCell<collections::HashMap<String, bool>>,
+}
+
+impl<'a, B: TelegramBackend> DialogInfo<'a, B> {
+    fn new(chat: grammers_client::types::Chat, backend: &'a B) -> Self {
+        Self {
+            chat,
+            backend,
+            about: cell::OnceCell::new(),
+            participants: cell::OnceCell::new(),
+            found_participants: cell::RefCell::new(collections::HashMap::new()),
+        }
+    }
+
+    fn chat(&self) -> &grammers_client::types::Chat {
+        &self.chat
+    }
+
+    async fn participant_usernames(&self) -> Result<&[String]> {
+        if let Some(participants) = self.participants.get() {
+            return Ok(participants);
+        }
+        let participants = self
+            .backend
+            .get_participants(self.chat())
+            .await?
+            .iter()
+            .filter_map(|participant| participant.username().map(str::to_owned))
+            .collect();
+        Ok(self.participants.get_or_init(|| participants))
+    }
+}
+
+impl<B: TelegramBackend> DialogProperties for DialogInfo<'_, B> {
+    fn dialog_type(&self) -> DialogType {
+        match self.chat() {
+            grammers_client::types::Chat::User(_) => DialogType::User,
+            grammers_client::types::Chat::Group(_) => DialogType::Group,
+            grammers_client::types::Chat::Channel(_) => DialogType::Channel,
+        }
+    }
+
+    fn id(&self) -> i64 {
+        self.chat().id()
+    }
+
+    fn title(&self) -> &str {
+        self.chat().name()
+    }
+
+    fn username(&self) -> Option<&str> {
+        self.chat().username()
+    }
+
+    fn usernames(&self) -> Vec<&str> {
+        // Main username is not included into collectible ones.
+        self.username()
+            .into_iter()
+            .chain(self.chat().usernames())
+            .collect()
+    }
+
+    fn flags(&self) -> Vec<String> {
+        chat_flags(self.chat())
+            .into_iter()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    async fn about(&self) -> Result<Option<String>> {
+        if let Some(about) = self.about.get() {
+            return Ok(about.clone());
+        }
+        let about = self.backend.get_chat_about(self.chat()).await?;
+        Ok(self.about.get_or_init(|| about).clone())
+    }
+
+    async fn has_participant(&self, login: &str) -> Result<bool> {
+        if let Some(participants) = self.participants.get() {
+            return Ok(participants.iter().any(|username| username == login));
+        }
+        if let Some(found) = self.found_participants.borrow().get(login) {
+            return Ok(*found);
+        }
+        // Search returns participants with login as part of name too, so
+        // usernames are compared exactly.
+        let found = self
+            .backend
+            .search_participants(self.chat(), login)
+            .await?
+            .iter()
+            .any(|participant| participant.username() == Some(login));
+        self.found_participants
+            .borrow_mut()
+            .insert(login.to_owned(), found);
+        Ok(found)
+    }
+
+    async fn participants(&self) -> Result<Vec<String>> {
+        Ok(self.participant_usernames().await?.to_vec())
+    }
+
+    async fn recent_messages(&self, limit: usize) -> Result<Vec<String>> {
+        self.backend.get_recent_messages(self.chat(), limit).await
+    }
+}
+
+fn channel_flags(channel: &tl_types::types::Channel) -> Vec<&'static str> {
+    [
+        (channel.creator, "creator"),
+        (channel.verified, "verified"),
+        (channel.scam, "scam"),
+        (channel.fake, "fake"),
+        (channel.megagroup, "megagroup"),
+        (channel.forum, "forum"),
+    ]
+    .into_iter()
+    .filter_map(|(set, flag)| set.then_some(flag))
+    .collect()
+}
+
+/// Returns flags of |chat|, set by Telegram, which external classifiers
+/// may use.
+pub(crate) fn chat_flags(chat: &grammers_client::types::Chat) -> Vec<&'static str> {
+    match chat {
+        grammers_client::types::Chat::User(user) => [
+            (user.raw.bot, "bot"),
+            (user.raw.verified, "verified"),
+            (user.raw.scam, "scam"),
+            (user.raw.fake, "fake"),
+            (user.raw.premium, "premium"),
+            (user.raw.contact, "contact"),
+            (user.raw.support, "support"),
+            (user.raw.deleted, "deleted"),
+        ]
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect(),
+        grammers_client::types::Chat::Group(group) => match &group.raw {
+            tl_types::enums::Chat::Chat(chat) if chat.creator => vec!["creator"],
+            tl_types::enums::Chat::Channel(channel) => channel_flags(channel),
+            _ => Vec::new(),
+        },
+        grammers_client::types::Chat::Channel(channel) => channel_flags(&channel.raw),
+    }
+}
+
+async fn assign_peers<B: TelegramBackend>(
+    backend: &B,
+    filter_name_to_dialogs: &collections::HashMap<String, Vec<tl_types::enums::InputPeer>>,
+) -> Result<utils::ApplyReport> {
+    let mut desired_filters = grammers_tl_types::types::messages::DialogFilters {
+        tags_enabled: false,
+        filters: Vec::new(),
+    };
+
+    for (name, peers) in filter_name_to_dialogs {
+        let new_item = grammers_tl_types::types::DialogFilterChatlist {
+            has_my_invites: false,
+            id: 0, // unused
+            title: name.to_owned(),
+            emoticon: None,
+            color: None,
+            pinned_peers: Vec::new(),
+            include_peers: peers.clone(),
+        };
+        desired_filters
+            .filters
+            .push(grammers_tl_types::enums::DialogFilter::Chatlist(new_item));
+    }
+    utils::apply_dialog_filters(backend, &desired_filters).await
+}
+
+/// Assigns account dialogs to folders according to |rules|. Dialogs already
+/// present in folders are kept there.
+pub async fn assign_dialogs<B: TelegramBackend>(
+    backend: &B,
+    rules: &ChatFilters,
+) -> Result<AssignReport> {
+    let mut filter_name_to_dialogs =
+        collections::HashMap::<String, Vec<tl_types::enums::InputPeer>>::new();
+    let mut assigned = collections::BTreeMap::<String, Vec<String>>::new();
+    info!("Collecting dialogs");
+    let dialog_infos: Vec<DialogInfo<B>> = backend
+        .get_dialogs()
+        .await?
+        .into_iter()
+        .map(|chat| DialogInfo::new(chat, backend))
+        .collect();
+    for (idx, dialog_info) in dialog_infos.iter().enumerate() {
+        debug!(
+            "Processing dialog {} of {} ({})",
+            idx + 1,
+            dialog_infos.len(),
+            dialog_info.chat().name()
+        );
+        let matched_filters = apply_rules(rules, dialog_info).await?;
+        for filter in matched_filters {
+            debug!("Assigned to folder {}", filter.name);
+            let items: &mut Vec<tl_types::enums::InputPeer> =
+                if let Some(v) = filter_name_to_dialogs.get_mut(&filter.name) {
+                    v
+                } else {
+                    filter_name_to_dialogs.insert(filter.name.clone(), Vec::new());
+                    filter_name_to_dialogs.get_mut(&filter.name).unwrap()
+                };
+            items.push(dialog_info.chat().pack().to_input_peer());
+            assigned
+                .entry(filter.name.clone())
+                .or_default()
+                .push(dialog_info.title().to_owned());
+        }
+    }
+    info!(
+        "Processed {} dialogs, assigning them to {} folders",
+        dialog_infos.len(),
+        assigned.len()
+    );
+    let folders = assign_peers(backend, &filter_name_to_dialogs).await?;
+    Ok(AssignReport {
+        dialogs_count: dialog_infos.len(),
+        assigned,
+        folders,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::{self, FakeBackend};
+
+    fn rules(json: &str) -> ChatFilters {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn account() -> FakeBackend {
+        let mut backend = FakeBackend::new(
+            1,
+            vec![
+                fake::channel_chat(100, "Robo news", Some("robonews")),
+                fake::group_chat(300, "Family"),
+                fake::channel_chat(101, "Weather", None),
+                fake::user_chat(200, "Bob", Some("bob")),
+            ],
+        );
+        backend
+            .participants
+            .insert(300, vec![fake::user(201, "Mom", Some("mom"))]);
+        backend
+            .abouts
+            .insert(101, "Forecasts for robots".to_owned());
+        backend
+    }
+
+    #[tokio::test]
+    async fn assign_creates_folders_by_rules() {
+        let backend = account();
+        let rules = rules(
+            r#"[
+                {"name": "Robots", "when": "title ~ /(?i)robo/ || about ~ /robot/"},
+                {"name": "Family", "when": "contact == \"mom\""},
+                {"name": "Other", "when": "!matched"}
+            ]"#,
+        );
+        assign_dialogs(&backend, &rules).await.unwrap();
+        let folder_ids = |title| fake::included_ids(&backend.filter_titled(title).unwrap());
+        assert_eq!(folder_ids("Robots"), vec![100, 101]);
+        assert_eq!(folder_ids("Family"), vec![300]);
+        assert_eq!(folder_ids("Other"), vec![200]);
+        assert_eq!(backend.filters.borrow().len(), 3);
+    }
+
+    #[test]
+    fn dialog_usernames_start_with_main_one() {
+        let backend = account();
+        let news = DialogInfo::new(
+            fake::channel_chat(100, "Robo news", Some("robonews")),
+            &backend,
+        );
+        assert_eq!(news.usernames(), vec!["robonews"]);
+        let weather = DialogInfo::new(fake::channel_chat(101, "Weather", None), &backend);
+        assert!(weather.usernames().is_empty());
+    }
+
+    #[tokio::test]
+    async fn assign_looks_up_each_participant_once_per_dialog() {
+        let backend = account();
+        let rules = rules(
+            r#"[
+                {"name": "Family", "when": "contact == \"dad\" || contact == \"mom\""},
+                {"name": "Mom", "when": "type == Group && contact == \"mom\""}
+            ]"#,
+        );
+        let report = assign_dialogs(&backend, &rules).await.unwrap();
+        assert_eq!(report.assigned["Family"], vec!["Family"]);
+        assert_eq!(report.assigned["Mom"], vec!["Family"]);
+        let searches = backend.participant_searches.borrow();
+        let family_searches: Vec<_> = searches.iter().filter(|(id, _)| *id == 300).collect();
+        assert_eq!(
+            family_searches,
+            vec![&(300, "dad".to_owned()), &(300, "mom".to_owned())]
+        );
+        assert!(backend.participant_requests.borrow().is_empty());
+    }
+
+    #[tokio::test]
+    async fn participant_lookup_compares_usernames_exactly() {
+        let mut backend = account();
+        backend
+            .participants
+            .insert(300, vec![fake::user(202, "Momo", Some("momo"))]);
+        let family = DialogInfo::new(fake::group_chat(300, "Family"), &backend);
+        assert!(!family.has_participant("mom").await.unwrap());
+        assert!(family.has_participant("momo").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn fetched_participants_are_used_for_lookup() {
+        let backend = account();
+        let family = DialogInfo::new(fake::group_chat(300, "Family"), &backend);
+        assert_eq!(family.participants().await.unwrap(), vec!["mom"]);
+        assert!(family.has_participant("mom").await.unwrap());
+        assert!(!family.has_participant("dad").await.unwrap());
+        assert!(backend.participant_searches.borrow().is_empty());
+        assert_eq!(*backend.participant_requests.borrow(), vec![300]);
+    }
+
+    #[tokio::test]
+    async fn assign_keeps_existing_folder_contents() {
+        let backend = account();
+        let alice = fake::user_chat(400, "Alice", None);
+        backend
+            .filters
+            .replace(vec![fake::chatlist(7, "Robots", &[&alice])]);
+        let rules = rules(r#"[{"name": "Robots", "when": "type == Channel"}]"#);
+        assign_dialogs(&backend, &rules).await.unwrap();
+        let filters = backend.filters.borrow();
+        assert_eq!(filters.len(), 1);
+        assert_eq!(utils::get_filter_id(&filters[0]), Some(7));
+        assert_eq!(fake::included_ids(&filters[0]), vec![100, 101, 400]);
+    }
+
+    #[tokio::test]
+    async fn assign_rejects_too_long_folder_names() {
+        let backend = account();
+        let rules = rules(r#"[{"name": "Very long folder name", "when": "type == User"}]"#);
+        assert!(assign_dialogs(&backend, &rules).await.is_err());
+        assert!(backend.filters.borrow().is_empty());
+    }
+
+    #[tokio::test]
+    async fn assign_reports_failed_folders() {
+        let mut backend = account();
+        backend.rejected_titles.push("Family".to_owned());
+        let rules = rules(
+            r#"[
+                {"name": "Family", "when": "contact == \"mom\""},
+                {"name": "Other", "when": "!matched"}
+            ]"#,
+        );
+        let report = assign_dialogs(&backend, &rules).await.unwrap();
+        assert_eq!(report.folders.created, vec!["Other"]);
+        assert_eq!(report.folders.failed.len(), 1);
+        assert_eq!(report.folders.failed[0].folder, "Family");
+        assert!(backend.filter_titled("Other").is_some());
+
+        backend.rejected_titles.push("Other".to_owned());
+        let error = assign_dialogs(&backend, &rules).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Failed to update any of folders"));
+        // Folders are applied in arbitrary order, so any of them is first.
+        assert!(error.root_cause().to_string().ends_with("is rejected"));
+    }
+
+    #[tokio::test]
+    async fn assign_rejects_rules_not_resolved_by_loader() {
+        let backend = account();
+        let reference = rules(r#"[{"name": "Work", "condition": {"ref": "work"}}]"#);
+        let error = assign_dialogs(&backend, &reference).await.unwrap_err();
+        assert!(error.to_string().contains("\"work\" is not resolved"));
+        let variable = rules(r#"[{"name": "Work", "when": "title ~ /${company}/"}]"#);
+        assert!(assign_dialogs(&backend, &variable).await.is_err());
+        assert!(backend.filter_titled("Work").is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn assign_uses_persistent_json_classifier() {
+        use std::os::unix::fs::PermissionsExt;
+        let mut backend = account();
+        backend
+            .messages
+            .insert(300, vec!["Robots are coming".to_owned()]);
+        let dir = std::env::temp_dir().join(format!("tg-tool-classifier-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("classify.sh");
+        // Logs started processes and requests, to check that one process
+        // serves all dialogs and is asked once per dialog for both rules.
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\nlog={}\necho started >> $log\n\
+                 while read -r dialog; do\n\
+                 echo request >> $log\n\
+                 case \"$dialog\" in\n\
+                 *Robo*) echo '{{\"folders\": [\"Robots\", \"News\"]}}' ;;\n\
+                 *'\"type\":\"channel\"'*) echo '{{\"score\": 0.6}}' ;;\n\
+                 *) echo '{{\"match\": false}}' ;;\n\
+                 esac\n\
+                 done\n",
+                dir.join("log").display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let rules_file = dir.join("rules.json");
+        std::fs::write(
+            &rules_file,
+            format!(
+                r#"{{
+                "definitions": {{"classifier": {{"condition": {{"external_executable": {{
+                    "path": "{}", "protocol": "json", "persistent": true, "include_messages": 1
+                }}}}}}}},
+                "rules": [
+                    {{"name": "Robots", "condition": {{"ref": "classifier"}}}},
+                    {{"name": "News", "condition": {{"ref": "classifier"}}}}
+                ]
+            }}"#,
+                script.display()
+            ),
+        )
+        .unwrap();
+        let rules = crate::rules::load_rules(&rules_file, None).unwrap();
+        let report = assign_dialogs(&backend, &rules).await.unwrap();
+        let log = std::fs::read_to_string(dir.join("log")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(log, format!("started\n{}", "request\n".repeat(4)));
+        assert_eq!(
+            report.assigned["Robots"],
+            vec!["Robo news", "Family", "Weather"]
+        );
+        assert_eq!(
+            report.assigned["News"],
+            vec!["Robo news", "Family", "Weather"]
+        );
+        assert!(!report
+            .assigned
+            .values()
+            .flatten()
+            .any(|title| title == "Bob"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn assign_limits_executable_run_time() {
+        use std::os::unix::fs::PermissionsExt;
+        let backend = account();
+        let dir = std::env::temp_dir().join(format!("tg-tool-executable-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("marker"), "").unwrap();
+        let script = dir.join("check.sh");
+        // Matches "Robo news" and hangs on "Bob", if started with stdin,
+        // environment and working directory of the condition.
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nread -r input\necho checking $1 >&2\n\
+             [ \"$input\" = hello ] && [ \"$GREETING\" = hi ] && [ -z \"$HOME\" ] \
+             && [ -f marker ] || exit 1\n\
+             case $1 in\n100) exit 0 ;;\n200) exec sleep 10 ;;\nesac\nexit 1\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let rules_json = |on_timeout: &str| {
+            serde_json::json!([{"name": "Robots", "condition": {"external_executable": {
+                "path": script, "params": ["@id@"], "stdin": "hello\n",
+                "env": {"GREETING": "hi", "PATH": "/usr/bin:/bin"}, "clear_env": true,
+                "cwd": dir, "timeout_ms": 500, "on_timeout": on_timeout
+            }}}])
+            .to_string()
+        };
+        let started = std::time::Instant::now();
+        let matching = assign_dialogs(&backend, &rules(&rules_json("match"))).await;
+        let not_matching = assign_dialogs(&backend, &rules(&rules_json("no_match"))).await;
+        let failing = assign_dialogs(&backend, &rules(&rules_json("error"))).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        assert_eq!(
+            matching.unwrap().assigned["Robots"],
+            vec!["Robo news", "Bob"]
+        );
+        assert_eq!(not_matching.unwrap().assigned["Robots"], vec!["Robo news"]);
+        assert!(failing.unwrap_err().to_string().contains("did not finish"));
+    }
+}
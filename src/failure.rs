@@ -0,0 +1,171 @@
+use grammers_client::client::auth::AuthorizationError;
+use grammers_client::InvocationError;
+use serde::Serialize;
+use std::fmt;
+use tg_tool::ApplyReport;
+
+/// Kind of command failure, defining process exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    Other,
+    // Invalid rules, backup or other input file.
+    Validation,
+    // Missing or unauthorized session.
+    Auth,
+    // Connection to Telegram failed.
+    Network,
+    // Some folders were not updated, while others were.
+    PartialFailure,
+}
+
+impl FailureKind {
+    // Code 2 is used by clap for command line errors.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailureKind::Other => 1,
+            FailureKind::Validation => 3,
+            FailureKind::Auth => 4,
+            FailureKind::Network => 5,
+            FailureKind::PartialFailure => 6,
+        }
+    }
+}
+
+/// Error with known kind. Other errors are classified by failure_kind.
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: FailureKind,
+    message: String,
+    // Command result, available despite the failure.
+    pub result: Option<serde_json::Value>,
+}
+
+impl Failure {
+    pub fn new(kind: FailureKind, message: impl fmt::Display) -> Self {
+        Self {
+            kind,
+            message: message.to_string(),
+            result: None,
+        }
+    }
+
+    pub fn with_result(mut self, result: serde_json::Value) -> Self {
+        self.result = Some(result);
+        self
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Marks |error| as caused by invalid input.
+pub fn validation(error: eyre::Report) -> eyre::Report {
+    Failure::new(FailureKind::Validation, error).into()
+}
+
+/// Marks |error| as caused by missing or unauthorized session.
+pub fn auth(error: eyre::Report) -> eyre::Report {
+    Failure::new(FailureKind::Auth, error).into()
+}
+
+fn invocation_failure_kind(error: &InvocationError) -> FailureKind {
+    match error {
+        // 401 is used for unregistered authorization key, revoked session
+        // and similar errors.
+        InvocationError::Rpc(rpc) if rpc.code == 401 => FailureKind::Auth,
+        InvocationError::Rpc(_) => FailureKind::Other,
+        InvocationError::Dropped | InvocationError::Read(_) => FailureKind::Network,
+    }
+}
+
+pub fn failure_kind(error: &eyre::Report) -> FailureKind {
+    for cause in error.chain() {
+        if let Some(failure) = cause.downcast_ref::<Failure>() {
+            return failure.kind;
+        }
+        if let Some(e) = cause.downcast_ref::<InvocationError>() {
+            return invocation_failure_kind(e);
+        }
+        if let Some(e) = cause.downcast_ref::<AuthorizationError>() {
+            return match e {
+                AuthorizationError::Gen(_) => FailureKind::Network,
+                AuthorizationError::Invoke(e) => invocation_failure_kind(e),
+            };
+        }
+    }
+    FailureKind::Other
+}
+
+/// Returns kind of failure of command run for |accounts_count| accounts,
+/// |failed| of which failed with given kinds, or None if none failed.
+pub fn batch_failure_kind(failed: &[FailureKind], accounts_count: usize) -> Option<FailureKind> {
+    let first = *failed.first()?;
+    // Failure of all accounts by the same reason keeps its kind, e.g. for
+    // lost network connection.
+    if failed.len() == accounts_count && failed.iter().all(|kind| *kind == first) {
+        Some(first)
+    } else {
+        Some(FailureKind::PartialFailure)
+    }
+}
+
+/// Returns JSON description of |error|: its kind, message and result of
+/// failed command, if any.
+pub fn error_json(error: &eyre::Report) -> (serde_json::Value, Option<serde_json::Value>) {
+    let description = serde_json::json!({
+        "kind": failure_kind(error),
+        "message": error.to_string(),
+    });
+    let result = error
+        .downcast_ref::<Failure>()
+        .and_then(|f| f.result.clone());
+    (description, result)
+}
+
+/// Returns |result| of command, which applied folders according to
+/// |report|, failing if some of them were not applied.
+pub fn check_applied(
+    report: &ApplyReport,
+    result: serde_json::Value,
+) -> eyre::Result<serde_json::Value> {
+    if report.failed.is_empty() {
+        return Ok(result);
+    }
+    let folders: Vec<&str> = report.failed.iter().map(|f| f.folder.as_str()).collect();
+    let message = format!("Failed to update folders {}", folders.join(", "));
+    Err(Failure::new(FailureKind::PartialFailure, message)
+        .with_result(result)
+        .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_failure_kind_is_kept_only_when_shared_by_all_accounts() {
+        use FailureKind::*;
+        assert_eq!(batch_failure_kind(&[], 3), None);
+        assert_eq!(batch_failure_kind(&[Network, Network], 2), Some(Network));
+        assert_eq!(batch_failure_kind(&[Auth], 1), Some(Auth));
+        // Other accounts succeeded.
+        assert_eq!(
+            batch_failure_kind(&[Network, Network], 3),
+            Some(PartialFailure)
+        );
+        assert_eq!(
+            batch_failure_kind(&[Auth, Network], 2),
+            Some(PartialFailure)
+        );
+        assert_eq!(
+            batch_failure_kind(&[PartialFailure, PartialFailure], 2),
+            Some(PartialFailure)
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use crate::backend::TelegramBackend;
+use crate::peers;
+use crate::serialization;
+use crate::utils;
+use eyre::Result;
+use grammers_tl_types as tl_types;
+use log::{info, warn};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct RestoreReport {
+    // Peers from backup, which were not found in the current account.
+    pub skipped_peers: Vec<String>,
+    pub folders: utils::ApplyReport,
+}
+
+/// Makes backup of account folders. If |include_access_hash| is set, peers
+/// access hashes are stored, so peers absent among dialogs can be restored
+/// on the same account.
+pub async fn backup_folders<B: TelegramBackend>(
+    backend: &B,
+    include_access_hash: bool,
+) -> Result<serialization::BackupFile> {
+    let me_id = backend.get_me_id().await?;
+    let filters = backend.get_dialog_filters().await?;
+    let directory = peers::PeerDirectory::load(backend).await?;
+    let folders = serialization::PortableDialogFilters::from_dialog_filters(&filters, |peer| {
+        directory.describe(peer, include_access_hash)
+    });
+    Ok(serialization::BackupFile::new(me_id, folders))
+}
+
+// Returns resolved filters and names of peers, which can not be found.
+// Stored access hashes are used only if |same_account| is set.
+async fn resolve_portable_filters<B: TelegramBackend>(
+    backend: &B,
+    portable_filters: &serialization::PortableDialogFilters,
+    same_account: bool,
+) -> Result<(tl_types::types::messages::DialogFilters, Vec<String>)> {
+    info!("Collecting dialogs");
+    let directory = peers::PeerDirectory::load(backend).await?;
+    let mut resolved = Vec::<(serialization::PortablePeer, tl_types::enums::InputPeer)>::new();
+    let mut skipped = Vec::<&serialization::PortablePeer>::new();
+    for peer in portable_filters.peers() {
+        if resolved.iter().any(|(p, _)| p == peer) || skipped.contains(&peer) {
+            continue;
+        }
+        match directory.resolve(backend, peer, same_account).await {
+            Some(input_peer) => resolved.push((peer.clone(), input_peer)),
+            None => {
+                warn!("Failed to find peer {}, skipping it", peer.display_name());
+                skipped.push(peer);
+            }
+        }
+    }
+    let filters = portable_filters.to_dialog_filters(|peer| {
+        resolved
+            .iter()
+            .find(|(p, _)| p == peer)
+            .map(|(_, input_peer)| input_peer.clone())
+    });
+    Ok((filters, skipped.iter().map(|p| p.display_name()).collect()))
+}
+
+/// Checks that folders of |backup| can be created, before connecting to
+/// Telegram.
+pub fn validate_backup(backup: &serialization::BackupFile) -> Result<()> {
+    utils::validate_folder_titles(backup.folders.titles())
+}
+
+/// Deletes all account folders. Returns titles of deleted folders.
+pub async fn clear_folders<B: TelegramBackend>(backend: &B) -> Result<Vec<String>> {
+    let filters = backend.get_dialog_filters().await?;
+    let mut deleted = Vec::new();
+    for filter in &filters.filters {
+        let maybe_filter_id = utils::get_filter_id(filter);
+        if let Some(filter_id) = maybe_filter_id {
+            backend.update_dialog_filter(filter_id, None).await?;
+            deleted.push(
+                utils::get_filter_title(filter)
+                    .unwrap_or_default()
+                    .to_owned(),
+            );
+        }
+    }
+    Ok(deleted)
+}
+
+/// Restores folders from |backup|, merging them with existing ones.
+pub async fn restore_folders<B: TelegramBackend>(
+    backend: &B,
+    backup: &serialization::BackupFile,
+) -> Result<RestoreReport> {
+    // Backups migrated from older formats have no account ID, and their
+    // access hashes were always stored, so they are trusted like before.
+    let same_account = match backup.account_id {
+        Some(account_id) if account_id != backend.get_me_id().await? => {
+            info!("Backup was made on account {account_id}, peers will be looked up by usernames and titles");
+            false
+        }
+        _ => true,
+    };
+    let (saved_filters, skipped_peers) =
+        resolve_portable_filters(backend, &backup.folders, same_account).await?;
+    let folders = utils::apply_dialog_filters(backend, &saved_filters).await?;
+    Ok(RestoreReport {
+        skipped_peers,
+        folders,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::{self, FakeBackend};
+    use crate::serialization::{PeerType, PortablePeer};
+
+    fn old_account() -> FakeBackend {
+        let news = fake::channel_chat(100, "News", Some("news"));
+        let alice = fake::user_chat(200, "Alice", Some("alice"));
+        let family = fake::group_chat(300, "Family");
+        let backend = FakeBackend::new(1, vec![news.clone(), alice.clone(), family.clone()]);
+        backend
+            .filters
+            .borrow_mut()
+            .push(fake::chatlist(2, "Main", &[&news, &alice, &family]));
+        backend
+    }
+
+    #[tokio::test]
+    async fn backup_describes_peers() {
+        let backup = backup_folders(&old_account(), false).await.unwrap();
+        assert_eq!(backup.account_id, Some(1));
+        let peers: Vec<&PortablePeer> = backup.folders.peers().collect();
+        let expected = [
+            (PeerType::Channel, 100, Some("news"), "News"),
+            (PeerType::User, 200, Some("alice"), "Alice"),
+            (PeerType::Chat, 300, None, "Family"),
+        ];
+        assert_eq!(peers.len(), expected.len());
+        for (peer, (peer_type, id, username, title)) in peers.iter().zip(expected) {
+            assert_eq!(peer.peer_type, peer_type);
+            assert_eq!(peer.id, id);
+            assert_eq!(peer.username.as_deref(), username);
+            assert_eq!(peer.title.as_deref(), Some(title));
+            assert_eq!(peer.access_hash, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn restore_on_other_account_finds_peers_by_username_and_title() {
+        let backup = backup_folders(&old_account(), false).await.unwrap();
+        let mut backend = FakeBackend::new(
+            2,
+            vec![
+                fake::channel_chat(101, "Renamed news", Some("news")),
+                fake::group_chat(301, "Family"),
+            ],
+        );
+        backend.public_chats = vec![fake::user_chat(201, "Alice", Some("alice"))];
+        restore_folders(&backend, &backup).await.unwrap();
+        let main = backend.filter_titled("Main").unwrap();
+        assert_eq!(fake::included_ids(&main), vec![101, 201, 301]);
+    }
+
+    #[tokio::test]
+    async fn restore_on_same_account_finds_peers_absent_among_dialogs() {
+        let backup = backup_folders(&old_account(), true).await.unwrap();
+        // Alice's dialog is deleted, and her username is not public.
+        let backend = FakeBackend::new(
+            1,
+            vec![
+                fake::channel_chat(100, "News", Some("news")),
+                fake::group_chat(300, "Family"),
+            ],
+        );
+        restore_folders(&backend, &backup).await.unwrap();
+        let main = backend.filter_titled("Main").unwrap();
+        assert_eq!(fake::included_ids(&main), vec![100, 200, 300]);
+    }
+
+    #[tokio::test]
+    async fn restore_on_other_account_ignores_access_hashes() {
+        let backup = backup_folders(&old_account(), true).await.unwrap();
+        assert_eq!(
+            backup.folders.peers().nth(1).unwrap().access_hash,
+            Some(2000)
+        );
+        let backend = FakeBackend::new(
+            2,
+            vec![
+                fake::channel_chat(100, "News", Some("news")),
+                fake::group_chat(300, "Family"),
+            ],
+        );
+        let report = restore_folders(&backend, &backup).await.unwrap();
+        assert_eq!(report.skipped_peers, vec!["@alice"]);
+        let main = backend.filter_titled("Main").unwrap();
+        assert_eq!(fake::included_ids(&main), vec![100, 300]);
+    }
+
+    #[tokio::test]
+    async fn restore_merges_into_existing_folder() {
+        let backup = backup_folders(&old_account(), true).await.unwrap();
+        let backend = old_account();
+        let bob = fake::user_chat(400, "Bob", None);
+        backend.filters.replace(vec![
+            fake::chatlist(2, "Work", &[]),
+            fake::chatlist(5, "Main", &[&bob]),
+        ]);
+        restore_folders(&backend, &backup).await.unwrap();
+        let filters = backend.filters.borrow();
+        assert_eq!(filters.len(), 2);
+        assert_eq!(utils::get_filter_id(&filters[1]), Some(5));
+        assert_eq!(fake::included_ids(&filters[1]), vec![100, 200, 300, 400]);
+    }
+
+    #[tokio::test]
+    async fn restore_creates_folders_with_new_ids() {
+        let source = old_account();
+        source
+            .filters
+            .borrow_mut()
+            .push(fake::chatlist(3, "Second", &[]));
+        let backup = backup_folders(&source, false).await.unwrap();
+        let backend = old_account();
+        backend
+            .filters
+            .replace(vec![fake::chatlist(3, "Work", &[])]);
+        restore_folders(&backend, &backup).await.unwrap();
+        let ids: Vec<Option<i32>> = backend
+            .filters
+            .borrow()
+            .iter()
+            .map(utils::get_filter_id)
+            .collect();
+        assert_eq!(ids, vec![Some(3), Some(4), Some(5)]);
+    }
+
+    #[tokio::test]
+    async fn clear_removes_all_folders() {
+        let backend = old_account();
+        backend
+            .filters
+            .borrow_mut()
+            .push(fake::chatlist(3, "Work", &[]));
+        clear_folders(&backend).await.unwrap();
+        assert!(backend.filters.borrow().is_empty());
+    }
+}
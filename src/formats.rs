@@ -0,0 +1,59 @@
+use eyre::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+use std::path;
+
+/// Format of rules and backup files.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum FileFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl FileFormat {
+    /// Returns |explicit| format if provided, otherwise guesses it by
+    /// extension of |path|, falling back to JSON.
+    pub fn detect(explicit: Option<FileFormat>, path: &path::Path) -> Self {
+        if let Some(format) = explicit {
+            return format;
+        }
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        match extension.as_deref() {
+            Some("yaml") | Some("yml") => FileFormat::Yaml,
+            Some("toml") => FileFormat::Toml,
+            _ => FileFormat::Json,
+        }
+    }
+
+    pub fn read<T: DeserializeOwned>(self, path: &path::Path) -> Result<T> {
+        let content = fs::read_to_string(path)?;
+        let result = match self {
+            FileFormat::Json => serde_json::from_str(&content)?,
+            FileFormat::Yaml => {
+                // serde_yaml expects enums written as YAML tags, go through
+                // JSON value to accept same single-key maps as in JSON.
+                let value: serde_json::Value = serde_yaml::from_str(&content)?;
+                serde_json::from_value(value)?
+            }
+            FileFormat::Toml => toml::from_str(&content)?,
+        };
+        Ok(result)
+    }
+
+    /// Writes |value| to |path|. |pretty| affects only JSON, since
+    /// other formats are always human-readable.
+    pub fn write<T: Serialize>(self, path: &path::Path, value: &T, pretty: bool) -> Result<()> {
+        let content = match self {
+            FileFormat::Json if pretty => serde_json::to_string_pretty(value)?,
+            FileFormat::Json => serde_json::to_string(value)?,
+            FileFormat::Yaml => serde_yaml::to_string(value)?,
+            FileFormat::Toml => toml::to_string_pretty(value)?,
+        };
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+//! Library behind tg-tool: backup and restore of Telegram folders and
+//! assignment of dialogs to folders by rules.
+//!
+//! Main entry points:
+//! - [rules::load_rules] loads rules file, [rules::apply_rules] evaluates
+//!   rules against any dialog implementing [rules::DialogProperties];
+//! - [dialogs::assign_dialogs] assigns account dialogs to folders by rules;
+//! - [folders::backup_folders] and [folders::restore_folders] produce and
+//!   apply [serialization::BackupFile];
+//! - [apply_dialog_filters] merges filters into account ones;
+//! - [chats] resolves chats by username and exports their participants,
+//!   which works for bot sessions too.
+//!
+//! Functions working with Telegram take [backend::TelegramBackend], use
+//! [backend::GrammersBackend] to work with connected grammers client.
+
+pub mod backend;
+pub mod chats;
+pub mod dialogs;
+pub mod folders;
+pub mod formats;
+mod peers;
+pub mod rules;
+pub mod serialization;
+mod utils;
+
+pub use utils::{apply_dialog_filters, ApplyReport, FolderFailure};
@@ -0,0 +1,184 @@
+use eyre::{eyre, Result};
+use log::{LevelFilter, Log};
+use simple_logger::SimpleLogger;
+use std::fs;
+use std::io::Write;
+use std::path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use time::format_description::BorrowedFormatItem;
+
+// Target prefix of log records made by this tool, both library and binary.
+const OWN_TARGET: &str = "tg_tool";
+// UTC timestamp of log records, both in stderr and in log file.
+const TIMESTAMP_FORMAT: &[BorrowedFormatItem] = time::macros::format_description!(
+    "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z"
+);
+
+/// Level of a module, given on command line as "module=level".
+#[derive(Clone, Debug)]
+pub struct ModuleLevel {
+    module: String,
+    level: LevelFilter,
+}
+
+impl FromStr for ModuleLevel {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (module, level) = s
+            .split_once('=')
+            .ok_or_else(|| eyre!("Expected module=level, got \"{s}\""))?;
+        let level = LevelFilter::from_str(level)
+            .map_err(|_| eyre!("Unknown log level \"{level}\" for module {module}"))?;
+        Ok(Self {
+            module: module.to_owned(),
+            level,
+        })
+    }
+}
+
+/// Logging options from command line.
+pub struct LogParams {
+    // Level of the tool before applying -v and -q.
+    pub level: LevelFilter,
+    // Difference between -v and -q occurrences.
+    pub verbosity: i8,
+    pub log_file: Option<path::PathBuf>,
+    pub module_levels: Vec<ModuleLevel>,
+}
+
+// Writes records enabled by |filter| to log file, in the same format as
+// simple_logger writes them to stderr.
+struct FileLogger {
+    filter: SimpleLogger,
+    file: Mutex<fs::File>,
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let timestamp = time::OffsetDateTime::now_utc()
+            .format(TIMESTAMP_FORMAT)
+            .unwrap_or_default();
+        // Logging must not fail the command, so write errors are ignored.
+        let _ = writeln!(
+            self.file.lock().unwrap(),
+            "{timestamp} {:<5} [{}] {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {
+        let _ = self.file.lock().unwrap().flush();
+    }
+}
+
+/// Returns logger configured by |params|: records of this tool are written
+/// at |params.level|, and ones of dependencies at Warn. Each -v raises and
+/// each -q lowers both levels by one step. Levels of modules override
+/// them, the most specific module wins.
+fn configure(params: &LogParams) -> SimpleLogger {
+    let shift = |level: LevelFilter| {
+        let idx = LevelFilter::iter().position(|l| l == level).unwrap() as i8;
+        let idx = (idx + params.verbosity).clamp(0, LevelFilter::Trace as i8);
+        LevelFilter::iter().nth(idx as usize).unwrap()
+    };
+    let logger = SimpleLogger::new()
+        .with_level(shift(LevelFilter::Warn))
+        .with_utc_timestamps()
+        .with_timestamp_format(TIMESTAMP_FORMAT);
+    // Of modules with equal names the first added wins, so level of the
+    // tool is added after levels given on command line.
+    params
+        .module_levels
+        .iter()
+        .fold(logger, |logger, m| {
+            logger.with_module_level(&m.module, m.level)
+        })
+        .with_module_level(OWN_TARGET, shift(params.level))
+}
+
+/// Installs logger according to |params|, writing to stderr or to log file.
+pub fn init(params: &LogParams) -> Result<()> {
+    let logger = configure(params);
+    let Some(log_file) = &params.log_file else {
+        return logger.init().map_err(|e| eyre!("{e}"));
+    };
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .map_err(|e| eyre!("Failed to open log file {}: {e}", log_file.display()))?;
+    log::set_max_level(logger.max_level());
+    log::set_boxed_logger(Box::new(FileLogger {
+        filter: logger,
+        file: Mutex::new(file),
+    }))
+    .map_err(|e| eyre!("{e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled(logger: &SimpleLogger, target: &str, level: log::Level) -> bool {
+        logger.enabled(&log::Metadata::builder().target(target).level(level).build())
+    }
+
+    #[test]
+    fn most_specific_module_level_is_used() {
+        let logger = configure(&LogParams {
+            level: LevelFilter::Info,
+            verbosity: 0,
+            log_file: None,
+            module_levels: vec![
+                "tg_tool::backend::grammers=trace".parse().unwrap(),
+                "tg_tool::backend=debug".parse().unwrap(),
+            ],
+        });
+        use log::Level::{Debug, Info, Trace, Warn};
+        assert!(enabled(&logger, "tg_tool::backend::grammers", Trace));
+        assert!(enabled(&logger, "tg_tool::backend::traffic", Debug));
+        assert!(!enabled(&logger, "tg_tool::backend::traffic", Trace));
+        assert!(enabled(&logger, "tg_tool::dialogs", Info));
+        assert!(!enabled(&logger, "tg_tool::dialogs", Debug));
+        assert!(enabled(&logger, "grammers_mtsender", Warn));
+        assert!(!enabled(&logger, "grammers_mtsender", Info));
+        let own = configure(&LogParams {
+            level: LevelFilter::Info,
+            verbosity: 0,
+            log_file: None,
+            module_levels: vec!["tg_tool=error".parse().unwrap()],
+        });
+        assert!(!enabled(&own, "tg_tool::dialogs", Warn));
+        assert!("tg_tool".parse::<ModuleLevel>().is_err());
+        assert!("tg_tool=loud".parse::<ModuleLevel>().is_err());
+    }
+
+    #[test]
+    fn verbosity_shifts_own_and_dependency_levels() {
+        let params = |verbosity| LogParams {
+            level: LevelFilter::Info,
+            verbosity,
+            log_file: None,
+            module_levels: Vec::new(),
+        };
+        use log::Level::{Debug, Error, Info, Warn};
+        let verbose = configure(&params(1));
+        assert!(enabled(&verbose, "tg_tool", Debug));
+        assert!(enabled(&verbose, "grammers_mtsender", Info));
+        let quiet = configure(&params(-2));
+        assert!(!enabled(&quiet, "tg_tool", Warn));
+        assert!(enabled(&quiet, "tg_tool", Error));
+        assert!(!enabled(&quiet, "grammers_mtsender", Error));
+    }
+}
@@ -1,118 +1,617 @@
-use clap::{Parser, Subcommand};
-use eyre::Result;
+use clap::{Parser, Subcommand, ValueEnum};
+use eyre::{eyre, Result};
 use log::{error, info};
+use std::fs;
 use std::path;
+use tg_tool::formats;
+mod api_credentials;
 mod commands;
-mod serialization;
-mod utils;
+mod config;
+mod failure;
+mod logging;
+mod proxy;
+mod qr;
+mod session_file;
 
 #[derive(Debug, Parser)]
 #[command(version, about = "Tool for command-line interaction with Telegram")]
 struct CliParams {
     #[command(subcommand)]
     command: Command,
+    /// Required for all commands, except "dialogs check-rules",
+    /// "dialogs test-rules" and commands run with --replay, unless given
+    /// by profile. May be repeated to run folders commands and
+    /// "dialogs assign" for several accounts.
     #[arg(long)]
-    session_file: path::PathBuf,
+    session_file: Vec<path::PathBuf>,
+    /// File with passphrase of encrypted session file. By default it is
+    /// taken from TG_TOOL_SESSION_PASSPHRASE variable or asked.
+    #[arg(long)]
+    session_key_file: Option<path::PathBuf>,
+    /// ID of Telegram API application, registered at my.telegram.org. Used
+    /// with --api-hash instead of application built into the tool, also
+    /// taken from TG_TOOL_API_ID variable or profile. Login stores it in
+    /// session file for later commands.
+    #[arg(long, requires = "api_hash")]
+    api_id: Option<i32>,
+    /// Hash of Telegram API application, see --api-id.
+    #[arg(long, requires = "api_id")]
+    api_hash: Option<String>,
+    /// Connect to Telegram through SOCKS5 proxy, given as
+    /// socks5://[user:password@]host:port. MTProxy is not supported.
+    #[arg(long)]
+    proxy: Option<proxy::ProxyUrl>,
+    /// Configuration file, by default ~/.config/tg-tool/config.toml.
+    #[arg(long)]
+    config: Option<path::PathBuf>,
+    /// Profile from configuration file, by default its "default_profile".
+    /// May be repeated, like --session-file.
+    #[arg(long)]
+    profile: Vec<String>,
+    /// Run command for all profiles listed in "accounts" of configuration
+    /// file.
+    #[arg(long, conflicts_with = "profile")]
+    all_accounts: bool,
+    /// Record requests to Telegram made by folders and dialogs commands,
+    /// with their responses, to this file, e.g. for attaching to bug
+    /// report. Requests made while connecting and checking session
+    /// authorization are not recorded.
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<path::PathBuf>,
+    /// Remove phone numbers and message texts from responses recorded
+    /// with --record.
+    #[arg(long, requires = "record")]
+    redact: bool,
+    /// Serve requests to Telegram from file written with --record instead
+    /// of connecting to Telegram. Changes are not applied to any account.
+    #[arg(long)]
+    replay: Option<path::PathBuf>,
+    /// Format of command result, printed to stdout. Logs are always
+    /// written to stderr.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    /// Log more details: -v logs each processed dialog and Telegram request
+    /// with its duration, -vv adds request contents and logs of Telegram
+    /// library.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+    /// Log only warnings, or only errors with -qq.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true, conflicts_with = "verbose")]
+    quiet: u8,
+    /// Append logs to this file instead of writing them to stderr.
+    #[arg(long)]
+    log_file: Option<path::PathBuf>,
+    /// Log level of a module, e.g. "tg_tool::backend=debug" or
+    /// "grammers_mtsender=trace". May be repeated.
+    #[arg(long, value_name = "MODULE=LEVEL")]
+    log_level: Vec<logging::ModuleLevel>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
 }
 
-#[derive(Debug, Subcommand)]
+#[derive(Clone, Debug, Subcommand)]
 enum Command {
     #[command(subcommand)]
     Folders(FoldersCommand),
     #[command(subcommand)]
     Dialogs(DialogsCommand),
-    Login,
+    #[command(subcommand)]
+    Session(SessionCommand),
+    #[command(subcommand)]
+    Chats(ChatsCommand),
+    /// Log in to Telegram. Credentials not given by options are taken
+    /// from TG_TOOL_PHONE, TG_TOOL_CODE and TG_TOOL_PASSWORD environment
+    /// variables, or asked interactively. Bot token may be given by
+    /// TG_TOOL_BOT_TOKEN variable, used unless --qr or --phone is given.
+    Login {
+        /// Log in by scanning QR code in Telegram app on other device,
+        /// instead of entering phone number and code.
+        #[arg(long, conflicts_with_all = ["phone", "code_file", "code_command"])]
+        qr: bool,
+        /// Log in as bot with token given by @BotFather. Folders and
+        /// dialogs commands are not available for bots. Visible to other
+        /// users in process list, prefer --bot-token-file or
+        /// TG_TOOL_BOT_TOKEN variable.
+        #[arg(
+            long,
+            conflicts_with_all = ["qr", "phone", "code_file", "code_command", "password_file"]
+        )]
+        bot_token: Option<String>,
+        /// File with bot token, like --bot-token.
+        #[arg(
+            long,
+            conflicts_with_all = [
+                "bot_token", "qr", "phone", "code_file", "code_command", "password_file"
+            ]
+        )]
+        bot_token_file: Option<path::PathBuf>,
+        /// Phone number in international format.
+        #[arg(long)]
+        phone: Option<String>,
+        /// File with login code, e.g. written by other process.
+        #[arg(long, conflicts_with = "code_command")]
+        code_file: Option<path::PathBuf>,
+        /// Shell command printing login code to stdout. Phone number is
+        /// passed in TG_TOOL_PHONE variable. Command is run again if code
+        /// is wrong.
+        #[arg(long)]
+        code_command: Option<String>,
+        /// File with two-step verification password.
+        #[arg(long)]
+        password_file: Option<path::PathBuf>,
+        /// Encrypt session file by passphrase, given like for reading
+        /// encrypted session by other commands. Implied when session key
+        /// file is given.
+        #[arg(long)]
+        encrypt: bool,
+        /// Overwrite session file holding logged in session.
+        #[arg(long)]
+        force: bool,
+    },
     Logout,
 }
 
-#[derive(Debug, Subcommand)]
+#[derive(Clone, Debug, Subcommand)]
+enum SessionCommand {
+    /// Show account the session belongs to: user ID, username, premium
+    /// status and DC.
+    Whoami,
+    /// List active sessions of the account, with device, IP region and
+    /// last activity time.
+    List,
+    /// Terminate other session of the account.
+    Terminate {
+        /// Hash of the session, shown by "session list".
+        #[arg(required_unless_present = "all_others", allow_negative_numbers = true)]
+        hash: Option<i64>,
+        /// Terminate all sessions except the current one.
+        #[arg(long, conflicts_with = "hash")]
+        all_others: bool,
+    },
+}
+
+/// Commands working with a single chat, given by username, with or
+/// without "@", or t.me link. Available for bot sessions too.
+#[derive(Clone, Debug, Subcommand)]
+enum ChatsCommand {
+    /// Show ID and type of chat.
+    Resolve { chat: String },
+    /// Show chat information, including its "about" description.
+    Info { chat: String },
+    /// Export IDs, usernames and names of group or channel participants.
+    /// Bots get participants of channels only where they are
+    /// administrators.
+    Participants { chat: String },
+}
+
+#[derive(Clone, Debug, Subcommand)]
 enum FoldersCommand {
     Backup {
         #[arg(long)]
         pretty: bool,
-        dst_file_path: path::PathBuf,
+        /// Do not store peer access hashes, which are valid only for the
+        /// current account, e.g. for sharing backup with others.
+        #[arg(long)]
+        portable: bool,
+        /// File format, by default chosen by file extension.
+        #[arg(long, value_enum)]
+        format: Option<formats::FileFormat>,
+        /// By default new file in "backup_dir" of profile.
+        dst_file_path: Option<path::PathBuf>,
     },
     Restore {
+        /// File format, by default chosen by file extension.
+        #[arg(long, value_enum)]
+        format: Option<formats::FileFormat>,
         src_file_path: path::PathBuf,
     },
     Clear,
 }
 
-#[derive(Debug, Subcommand)]
+#[derive(Clone, Debug, Subcommand)]
 enum DialogsCommand {
-    Assign { rules_file_path: path::PathBuf },
+    Assign {
+        /// File format, by default chosen by file extension.
+        #[arg(long, value_enum)]
+        format: Option<formats::FileFormat>,
+        /// By default "rules_file" of profile.
+        rules_file_path: Option<path::PathBuf>,
+    },
+    /// Check rules file for problems without connecting to Telegram.
+    CheckRules {
+        /// File format, by default chosen by file extension.
+        #[arg(long, value_enum)]
+        format: Option<formats::FileFormat>,
+        /// Fail on warnings too, not only on errors.
+        #[arg(long)]
+        deny_warnings: bool,
+        /// By default "rules_file" of profile.
+        rules_file_path: Option<path::PathBuf>,
+    },
+    /// Check assignment of fake dialogs from fixtures file to folders,
+    /// without connecting to Telegram.
+    TestRules {
+        /// Rules file format, by default chosen by file extension.
+        #[arg(long, value_enum)]
+        format: Option<formats::FileFormat>,
+        /// Fixtures file format, by default chosen by file extension.
+        #[arg(long, value_enum)]
+        fixtures_format: Option<formats::FileFormat>,
+        rules_file_path: path::PathBuf,
+        fixtures_file_path: path::PathBuf,
+    },
+}
+
+fn require_session_file(session_file: Option<&path::Path>) -> Result<&path::Path> {
+    session_file.ok_or_else(|| {
+        failure::validation(eyre!("--session-file option is required for this command"))
+    })
+}
+
+/// Returns |explicit| path of rules file, or one from |profile|.
+fn rules_file(explicit: Option<path::PathBuf>, profile: &config::Profile) -> Result<path::PathBuf> {
+    explicit
+        .or_else(|| profile.rules_file.clone())
+        .ok_or_else(|| {
+            failure::validation(eyre!(
+                "Rules file is required, unless \"rules_file\" is set in profile"
+            ))
+        })
+}
+
+/// Returns |explicit| path of backup file, or new file in backup directory
+/// of |profile|, named by current time. In batch mode, file name contains
+/// |batch_account| name, and |explicit| path is treated as directory.
+fn backup_file(
+    explicit: Option<path::PathBuf>,
+    profile: &config::Profile,
+    format: Option<formats::FileFormat>,
+    batch_account: Option<&str>,
+) -> Result<path::PathBuf> {
+    let backup_dir = match (explicit, batch_account) {
+        (Some(explicit), None) => return Ok(explicit),
+        (Some(explicit), Some(_)) => explicit,
+        (None, _) => profile.backup_dir.clone().ok_or_else(|| {
+            failure::validation(eyre!(
+                "Backup file is required, unless \"backup_dir\" is set in profile"
+            ))
+        })?,
+    };
+    fs::create_dir_all(&backup_dir)?;
+    let timestamp_format =
+        time::format_description::parse("[year][month][day]-[hour][minute][second]")?;
+    let timestamp = time::OffsetDateTime::now_utc().format(&timestamp_format)?;
+    let extension = match format {
+        Some(formats::FileFormat::Yaml) => "yaml",
+        Some(formats::FileFormat::Toml) => "toml",
+        Some(formats::FileFormat::Json) | None => "json",
+    };
+    let file_name = match batch_account {
+        // Account name may be path of session file.
+        Some(account) => {
+            let account = account.replace(|c: char| !c.is_alphanumeric() && c != '-', "_");
+            format!("folders-{account}-{timestamp}.{extension}")
+        }
+        None => format!("folders-{timestamp}.{extension}"),
+    };
+    Ok(backup_dir.join(file_name))
 }
 
 fn handle_folders_command(
     tokio_rt: &tokio::runtime::Runtime,
-    session_file: &path::Path,
+    backend_params: &commands::BackendParams,
+    profile: &config::Profile,
+    batch_account: Option<&str>,
     folders_cmd: FoldersCommand,
-) -> Result<()> {
+) -> Result<serde_json::Value> {
     match folders_cmd {
         FoldersCommand::Backup {
             dst_file_path,
             pretty,
+            portable,
+            format,
         } => tokio_rt.block_on(commands::handle_folders_backup_command(
-            session_file,
-            &dst_file_path,
+            backend_params,
+            &backup_file(dst_file_path, profile, format, batch_account)?,
             pretty,
-        ))?,
-        FoldersCommand::Restore { src_file_path } => tokio_rt.block_on(
-            commands::handle_folders_restore_command(session_file, &src_file_path),
-        )?,
+            !portable,
+            format,
+        )),
+        FoldersCommand::Restore {
+            src_file_path,
+            format,
+        } => tokio_rt.block_on(commands::handle_folders_restore_command(
+            backend_params,
+            &src_file_path,
+            format,
+        )),
         FoldersCommand::Clear => {
-            tokio_rt.block_on(commands::handle_folders_clear_command(session_file))?
+            tokio_rt.block_on(commands::handle_folders_clear_command(backend_params))
         }
     }
-    Ok(())
 }
 
 fn handle_dialogs_command(
     tokio_rt: &tokio::runtime::Runtime,
-    session_file: &path::Path,
+    backend_params: &commands::BackendParams,
+    profile: &config::Profile,
     dialogs_cmd: DialogsCommand,
-) -> Result<()> {
+) -> Result<serde_json::Value> {
     match dialogs_cmd {
-        DialogsCommand::Assign { rules_file_path } => tokio_rt.block_on(
-            commands::handle_dialogs_assign_command(session_file, &rules_file_path),
-        )?,
+        DialogsCommand::Assign {
+            rules_file_path,
+            format,
+        } => tokio_rt.block_on(commands::handle_dialogs_assign_command(
+            backend_params,
+            &rules_file(rules_file_path, profile)?,
+            format,
+        )),
+        DialogsCommand::CheckRules {
+            rules_file_path,
+            format,
+            deny_warnings,
+        } => commands::handle_dialogs_check_rules_command(
+            &rules_file(rules_file_path, profile)?,
+            format,
+            deny_warnings,
+        ),
+        DialogsCommand::TestRules {
+            format,
+            fixtures_format,
+            rules_file_path,
+            fixtures_file_path,
+        } => tokio_rt.block_on(commands::handle_dialogs_test_rules_command(
+            &rules_file_path,
+            &fixtures_file_path,
+            format,
+            fixtures_format,
+        )),
     }
-    Ok(())
 }
 
-fn do_main() -> Result<()> {
-    let params = CliParams::parse();
-    let tokio_rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .unwrap();
-    match params.command {
-        Command::Login => {
-            tokio_rt.block_on(commands::handle_login_command(&params.session_file))?;
+/// Runs |command| for |account|. |batch| tells whether the command is run
+/// for several accounts.
+fn run_command(
+    tokio_rt: &tokio::runtime::Runtime,
+    params: &CliParams,
+    account: &config::Account,
+    batch: bool,
+) -> Result<serde_json::Value> {
+    let profile = &account.profile;
+    let session_key = session_file::SessionKeyParams {
+        key_file: params
+            .session_key_file
+            .clone()
+            .or_else(|| profile.session_key_file.clone()),
+    };
+    let api = api_credentials::ApiCredentials::explicit(
+        params.api_id,
+        params.api_hash.as_deref(),
+        profile,
+    )?;
+    let proxy = params.proxy.clone().or_else(|| profile.proxy.clone());
+    let backend_params = commands::BackendParams {
+        session_file: profile.session_file.clone(),
+        session_key: session_key.clone(),
+        api: api.clone(),
+        proxy: proxy.clone(),
+        record_file: params.record.clone(),
+        redact: params.redact,
+        replay_file: params.replay.clone(),
+        request_interval: profile.request_interval(),
+    };
+    let session_file = profile.session_file.as_deref();
+    match params.command.clone() {
+        Command::Login {
+            qr,
+            bot_token,
+            bot_token_file,
+            phone,
+            code_file,
+            code_command,
+            password_file,
+            encrypt,
+            force,
+        } => {
+            let session_file = require_session_file(session_file)?;
+            let login_params = commands::LoginParams {
+                qr,
+                phone,
+                code_file,
+                code_command,
+                password_file,
+                encrypt,
+                api,
+                force,
+                proxy,
+                bot_token,
+                bot_token_file,
+            };
+            tokio_rt.block_on(commands::handle_login_command(
+                session_file,
+                &login_params,
+                &session_key,
+            ))
         }
         Command::Logout => {
-            tokio_rt.block_on(commands::handle_logout_command(&params.session_file))?;
-        }
-        Command::Folders(folders_cmd) => {
-            handle_folders_command(&tokio_rt, &params.session_file, folders_cmd)?;
+            let session_file = require_session_file(session_file)?;
+            tokio_rt.block_on(commands::handle_logout_command(
+                session_file,
+                &backend_params,
+            ))
         }
+        Command::Folders(folders_cmd) => handle_folders_command(
+            tokio_rt,
+            &backend_params,
+            profile,
+            batch.then_some(account.name.as_str()),
+            folders_cmd,
+        ),
         Command::Dialogs(dialogs_cmd) => {
-            handle_dialogs_command(&tokio_rt, &params.session_file, dialogs_cmd)?;
+            handle_dialogs_command(tokio_rt, &backend_params, profile, dialogs_cmd)
         }
+        Command::Session(session_cmd) => match session_cmd {
+            SessionCommand::Whoami => {
+                tokio_rt.block_on(commands::handle_session_whoami_command(&backend_params))
+            }
+            SessionCommand::List => {
+                tokio_rt.block_on(commands::handle_session_list_command(&backend_params))
+            }
+            SessionCommand::Terminate { hash, .. } => tokio_rt.block_on(
+                commands::handle_session_terminate_command(&backend_params, hash),
+            ),
+        },
+        Command::Chats(chats_cmd) => match chats_cmd {
+            ChatsCommand::Resolve { chat } => tokio_rt.block_on(
+                commands::handle_chats_resolve_command(&backend_params, &chat),
+            ),
+            ChatsCommand::Info { chat } => {
+                tokio_rt.block_on(commands::handle_chats_info_command(&backend_params, &chat))
+            }
+            ChatsCommand::Participants { chat } => tokio_rt.block_on(
+                commands::handle_chats_participants_command(&backend_params, &chat),
+            ),
+        },
     }
-    Ok(())
 }
 
-fn main() {
-    simple_logger::init_with_level(log::Level::Info).unwrap();
+/// Runs |params.command| for each of |accounts|, continuing after failures.
+/// Returns per-account results, failing if command failed for some of them.
+fn run_batch(
+    tokio_rt: &tokio::runtime::Runtime,
+    params: &CliParams,
+    accounts: &[config::Account],
+) -> Result<serde_json::Value> {
+    let batch_command = matches!(
+        params.command,
+        Command::Folders(_)
+            | Command::Dialogs(DialogsCommand::Assign { .. })
+            | Command::Session(SessionCommand::Whoami | SessionCommand::List)
+    );
+    if !batch_command {
+        return Err(failure::validation(eyre!(
+            "Only folders commands, \"dialogs assign\", \"session whoami\" and \"session list\" may be run for several accounts"
+        )));
+    }
+    if params.record.is_some() || params.replay.is_some() {
+        return Err(failure::validation(eyre!(
+            "--record and --replay may be used only with single account"
+        )));
+    }
+    let mut results = Vec::new();
+    let mut failures = Vec::new();
+    for account in accounts {
+        info!("Running command for account {}", account.name);
+        match run_command(tokio_rt, params, account, true) {
+            Ok(result) => {
+                info!("Account {}: success", account.name);
+                results.push(serde_json::json!({
+                    "account": account.name,
+                    "status": "ok",
+                    "result": result,
+                }));
+            }
+            Err(e) => {
+                error!("Account {}: error {e}", account.name);
+                let (description, result) = failure::error_json(&e);
+                results.push(serde_json::json!({
+                    "account": account.name,
+                    "status": "error",
+                    "error": description,
+                    "result": result,
+                }));
+                failures.push((account.name.as_str(), failure::failure_kind(&e)));
+            }
+        }
+    }
+    let result = serde_json::json!({ "accounts": results });
+    let kinds: Vec<failure::FailureKind> = failures.iter().map(|f| f.1).collect();
+    let Some(kind) = failure::batch_failure_kind(&kinds, accounts.len()) else {
+        return Ok(result);
+    };
+    let names: Vec<&str> = failures.iter().map(|f| f.0).collect();
+    let message = format!("Command failed for accounts {}", names.join(", "));
+    Err(failure::Failure::new(kind, message)
+        .with_result(result)
+        .into())
+}
 
-    match do_main() {
-        Ok(_) => {
+fn do_main(params: CliParams, accounts: &[config::Account]) -> Result<serde_json::Value> {
+    let tokio_rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    match accounts {
+        [account] => run_command(&tokio_rt, &params, account, false),
+        _ => run_batch(&tokio_rt, &params, accounts),
+    }
+}
+
+// Prints response of command failed with |error| to stdout.
+fn print_error_json(error: &eyre::Report) {
+    let (description, result) = failure::error_json(error);
+    let mut response = serde_json::json!({
+        "status": "error",
+        "error": description,
+    });
+    if let Some(result) = result {
+        response["result"] = result;
+    }
+    println!("{response}");
+}
+
+// Reports |error| of invalid configuration, found before logging is
+// initialized, and exits.
+fn exit_invalid_config(output: OutputFormat, error: eyre::Report) -> ! {
+    let error = failure::validation(error);
+    if output == OutputFormat::Json {
+        print_error_json(&error);
+    }
+    eprintln!("Error {error}");
+    std::process::exit(failure::FailureKind::Validation.exit_code());
+}
+
+fn main() {
+    let params = CliParams::parse();
+    let accounts = config::load_config(params.config.as_deref()).and_then(|config| {
+        config.accounts(&params.profile, params.all_accounts, &params.session_file)
+    });
+    let accounts = match accounts {
+        Ok(accounts) => accounts,
+        Err(e) => exit_invalid_config(params.output, e),
+    };
+    // Log level is common for all accounts, so it is taken from the first.
+    let log_params = logging::LogParams {
+        level: accounts[0]
+            .profile
+            .log_level
+            .unwrap_or(log::LevelFilter::Info),
+        verbosity: params.verbose as i8 - params.quiet as i8,
+        log_file: params.log_file.clone(),
+        module_levels: params.log_level.clone(),
+    };
+    if let Err(e) = logging::init(&log_params) {
+        exit_invalid_config(params.output, e);
+    }
+    let output = params.output;
+    match do_main(params, &accounts) {
+        Ok(result) => {
+            if output == OutputFormat::Json {
+                println!("{}", serde_json::json!({"status": "ok", "result": result}));
+            }
             info!("Success")
         }
         Err(e) => {
+            let kind = failure::failure_kind(&e);
+            if output == OutputFormat::Json {
+                print_error_json(&e);
+            }
             error!("Error {e}");
-            std::process::exit(1);
+            std::process::exit(kind.exit_code());
         }
     }
 }
@@ -0,0 +1,158 @@
+use crate::backend::TelegramBackend;
+use crate::serialization::{PeerType, PortablePeer};
+use eyre::Result;
+use grammers_client::session::PackedType;
+use grammers_client::types::{Chat, PackedChat};
+use grammers_tl_types as tl_types;
+use log::warn;
+use std::collections;
+
+fn packed_peer_type(packed: &PackedChat) -> PeerType {
+    match packed.ty {
+        PackedType::User | PackedType::Bot => PeerType::User,
+        PackedType::Chat => PeerType::Chat,
+        PackedType::Megagroup | PackedType::Broadcast | PackedType::Gigagroup => PeerType::Channel,
+    }
+}
+
+fn chat_title(chat: &Chat) -> String {
+    match chat {
+        Chat::User(user) => user.full_name(),
+        _ => chat.name().to_owned(),
+    }
+}
+
+/// Information about all peers present in account dialogs.
+/// Used to convert raw peers to the account-independent form and back.
+pub struct PeerDirectory {
+    chats: collections::HashMap<(PeerType, i64), Chat>,
+    by_username: collections::HashMap<String, (PeerType, i64)>,
+}
+
+impl PeerDirectory {
+    pub async fn load<B: TelegramBackend>(backend: &B) -> Result<Self> {
+        Ok(Self::from_chats(backend.get_dialogs().await?))
+    }
+
+    pub fn from_chats(chats: impl IntoIterator<Item = Chat>) -> Self {
+        let mut result = Self {
+            chats: collections::HashMap::new(),
+            by_username: collections::HashMap::new(),
+        };
+        for chat in chats {
+            let key = (packed_peer_type(&chat.pack()), chat.id());
+            // Main username is not included into collectible ones.
+            for username in chat.username().into_iter().chain(chat.usernames()) {
+                result.by_username.insert(username.to_lowercase(), key);
+            }
+            result.chats.insert(key, chat);
+        }
+        result
+    }
+
+    /// Describes |peer|, adding username and title if peer is known.
+    pub fn describe(
+        &self,
+        peer: &tl_types::enums::InputPeer,
+        include_access_hash: bool,
+    ) -> Option<PortablePeer> {
+        let mut result = PortablePeer::from_input_peer(peer)?;
+        if let Some(chat) = self.chats.get(&(result.peer_type, result.id)) {
+            result.username = chat.username().map(|s| s.to_owned());
+            result.title = Some(chat_title(chat));
+        } else if result.peer_type != PeerType::Myself {
+            warn!(
+                "Peer {:?} {} is not found among dialogs, only its ID is saved",
+                result.peer_type, result.id
+            );
+        }
+        if !include_access_hash {
+            result.access_hash = None;
+        }
+        Some(result)
+    }
+
+    fn find_by_title(&self, peer_type: PeerType, title: &str) -> Option<&Chat> {
+        let mut candidates = self
+            .chats
+            .iter()
+            .filter(|((t, _), chat)| *t == peer_type && chat_title(chat) == title);
+        let first = candidates.next();
+        if candidates.next().is_some() {
+            warn!("Several dialogs have title \"{title}\", can not choose between them");
+            return None;
+        }
+        first.map(|(_, chat)| chat)
+    }
+
+    /// Finds peer, valid for the current account, matching |peer|.
+    /// Tries, in order: same ID among dialogs, stored access hash if backup
+    /// was made on the same account (|same_account|), same username among
+    /// dialogs, username resolution on the Telegram server and unique title
+    /// among dialogs.
+    pub async fn resolve<B: TelegramBackend>(
+        &self,
+        backend: &B,
+        peer: &PortablePeer,
+        same_account: bool,
+    ) -> Option<tl_types::enums::InputPeer> {
+        if peer.peer_type == PeerType::Myself {
+            return peer.to_input_peer();
+        }
+        if let Some(chat) = self.chats.get(&(peer.peer_type, peer.id)) {
+            return Some(chat.pack().to_input_peer());
+        }
+        // Access hash is checked before usernames, since username may be
+        // taken by other peer after backup was made.
+        if same_account {
+            if let Some(input_peer) = peer.to_input_peer() {
+                return Some(input_peer);
+            }
+        }
+        if let Some(username) = &peer.username {
+            if let Some(key) = self.by_username.get(&username.to_lowercase()) {
+                return Some(self.chats[key].pack().to_input_peer());
+            }
+            match backend.resolve_username(username).await {
+                Ok(Some(chat)) => {
+                    return Some(chat.pack().to_input_peer());
+                }
+                Ok(None) => {
+                    warn!("Username @{username} is not occupied");
+                }
+                Err(e) => {
+                    warn!("Failed resolve username @{username}; error {e}");
+                }
+            }
+        }
+        let title = peer.title.as_ref()?;
+        self.find_by_title(peer.peer_type, title)
+            .map(|chat| chat.pack().to_input_peer())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::fake::{self, FakeBackend};
+
+    #[tokio::test]
+    async fn peers_are_resolved_by_main_username() {
+        let directory =
+            PeerDirectory::from_chats(vec![fake::channel_chat(100, "Robo news", Some("robonews"))]);
+        // Backend knows no chats, so peer is found only in the directory.
+        let backend = FakeBackend::new(1, Vec::new());
+        let peer = PortablePeer {
+            peer_type: PeerType::Channel,
+            id: 999,
+            username: Some("RoboNews".to_owned()),
+            title: None,
+            access_hash: None,
+        };
+        let resolved = directory.resolve(&backend, &peer, false).await.unwrap();
+        assert_eq!(
+            PortablePeer::from_input_peer(&resolved).map(|peer| peer.id),
+            Some(100)
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! Rendering of QR codes with login URLs in terminal.
+
+use eyre::{eyre, Result};
+use qrcode::render::unicode::Dense1x2;
+use std::io::IsTerminal;
+
+/// Returns QR code of |data| drawn by block characters, two modules per
+/// character, with quiet zone required by the standard. Dark modules are
+/// drawn by blocks, so on terminal the code is colored black on white to be
+/// scanned regardless of terminal color scheme.
+pub fn render(data: &[u8]) -> Result<String> {
+    let code = qrcode::QrCode::new(data).map_err(|e| eyre!("Failed to make QR code: {e}"))?;
+    let image = code.render::<Dense1x2>().build();
+    if !std::io::stderr().is_terminal() {
+        return Ok(image);
+    }
+    let lines: Vec<String> = image
+        .lines()
+        .map(|line| format!("\x1b[30;107m{line}\x1b[0m"))
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_has_quiet_zone() {
+        let url = format!("tg://login?token={}", "A".repeat(43));
+        let code = qrcode::QrCode::new(url.as_bytes()).unwrap();
+        let image = code.render::<Dense1x2>().build();
+        let lines: Vec<&str> = image.lines().collect();
+        let width = code.width() + 8;
+        assert!(lines.iter().all(|line| line.chars().count() == width));
+        assert_eq!(lines.len(), width.div_ceil(2));
+        // Top 4 rows of modules are light.
+        assert!(lines[..2].iter().all(|line| line.trim().is_empty()));
+        assert!(lines[2].contains('\u{2588}'));
+    }
+}
@@ -0,0 +1,364 @@
+//! JSON protocol of external_executable condition. Executable gets JSON
+//! description of dialog on stdin and prints JSON result on stdout. In
+//! persistent mode one process gets all dialogs, one JSON line per dialog,
+//! answering each with one line.
+
+use super::engine::DialogProperties;
+use super::executable;
+use super::{AssignConditionExternalExecutable, DialogType};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::process;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+// Used when condition does not set "min_score".
+const DEFAULT_MIN_SCORE: f64 = 0.5;
+
+#[derive(Serialize)]
+struct DialogDescription<'a> {
+    #[serde(rename = "type")]
+    dialog_type: &'static str,
+    id: i64,
+    title: &'a str,
+    username: Option<&'a str>,
+    usernames: Vec<&'a str>,
+    about: Option<String>,
+    flags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    participants: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    messages: Option<Vec<String>>,
+}
+
+async fn describe<D: DialogProperties>(
+    condition_info: &AssignConditionExternalExecutable,
+    dialog: &D,
+) -> Result<String> {
+    let participants = if condition_info.include_participants {
+        Some(dialog.participants().await?)
+    } else {
+        None
+    };
+    let messages = if condition_info.include_messages > 0 {
+        Some(
+            dialog
+                .recent_messages(condition_info.include_messages)
+                .await?,
+        )
+    } else {
+        None
+    };
+    let description = DialogDescription {
+        dialog_type: match dialog.dialog_type() {
+            DialogType::User => "user",
+            DialogType::Group => "group",
+            DialogType::Channel => "channel",
+        },
+        id: dialog.id(),
+        title: dialog.title(),
+        username: dialog.username(),
+        usernames: dialog.usernames(),
+        about: dialog.about().await?,
+        flags: dialog.flags(),
+        participants,
+        messages,
+    };
+    Ok(serde_json::to_string(&description)?)
+}
+
+/// Result printed by executable: whether dialog matches, folders it belongs
+/// to, or its score.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct Response {
+    #[serde(rename = "match")]
+    matched: Option<bool>,
+    folders: Option<Vec<String>>,
+    score: Option<f64>,
+}
+
+impl Response {
+    fn parse(output: &str) -> Result<Self> {
+        let response: Self = serde_json::from_str(output.trim())
+            .map_err(|e| eyre!("Invalid response {:?}; {e}", output.trim()))?;
+        let given = [
+            response.matched.is_some(),
+            response.folders.is_some(),
+            response.score.is_some(),
+        ];
+        if given.iter().filter(|given| **given).count() != 1 {
+            return Err(eyre!(
+                "Response {:?} must have exactly one of \"match\", \"folders\" and \"score\"",
+                output.trim()
+            ));
+        }
+        Ok(response)
+    }
+
+    fn matches(&self, rule_name: &str, min_score: f64) -> bool {
+        match self {
+            Self {
+                matched: Some(matched),
+                ..
+            } => *matched,
+            Self {
+                folders: Some(folders),
+                ..
+            } => folders.iter().any(|folder| folder == rule_name),
+            Self {
+                score: Some(score), ..
+            } => *score >= min_score,
+            _ => false,
+        }
+    }
+}
+
+struct PersistentProcess {
+    // Killed when dropped.
+    _child: tokio::process::Child,
+    stdin: tokio::process::ChildStdin,
+    stdout: tokio::io::BufReader<tokio::process::ChildStdout>,
+}
+
+// IDs of users and of chats may coincide, so type is a part of the key.
+type DialogKey = (DialogType, i64, String);
+
+#[derive(Default)]
+struct StateData {
+    process: Option<PersistentProcess>,
+    // Response for the last dialog, identified by type, ID and title, so
+    // the same executable used by several rules is run once per dialog.
+    last_response: Option<(DialogKey, Response)>,
+}
+
+/// State of external executable condition with JSON protocol, shared by
+/// copies of the condition, e.g. referencing the same definition.
+#[derive(Clone, Default)]
+pub struct ClassifierState(Arc<Mutex<StateData>>);
+
+// Returns None if executable does not finish in time.
+async fn run_once(
+    condition_info: &AssignConditionExternalExecutable,
+    input: &str,
+) -> Result<Option<String>> {
+    let input = format!("{input}\n");
+    let Some(output) =
+        executable::run(condition_info, &condition_info.params, Some(&input)).await?
+    else {
+        return Ok(None);
+    };
+    if !output.status.success() {
+        return Err(eyre!("Executable failed with {}", output.status));
+    }
+    Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+}
+
+// Returns None if executable does not respond in time.
+async fn run_persistent(
+    condition_info: &AssignConditionExternalExecutable,
+    process: &mut Option<PersistentProcess>,
+    input: &str,
+) -> Result<Option<String>> {
+    if process.is_none() {
+        let mut child = executable::command(condition_info)
+            .args(&condition_info.params)
+            .stdin(process::Stdio::piped())
+            .spawn()?;
+        executable::forward_stderr(
+            &condition_info.path,
+            child.stderr.take().expect("stderr is piped"),
+        );
+        *process = Some(PersistentProcess {
+            stdin: child.stdin.take().expect("stdin is piped"),
+            stdout: tokio::io::BufReader::new(child.stdout.take().expect("stdout is piped")),
+            _child: child,
+        });
+    }
+    let running = process.as_mut().unwrap();
+    let exchange = async {
+        running.stdin.write_all(input.as_bytes()).await?;
+        running.stdin.write_all(b"\n").await?;
+        running.stdin.flush().await?;
+        let mut line = String::new();
+        if running.stdout.read_line(&mut line).await? == 0 {
+            return Err(eyre!("Executable exited without response"));
+        }
+        Ok(line)
+    };
+    executable::with_timeout(condition_info, exchange)
+        .await
+        .transpose()
+}
+
+/// Result of asking executable about one dialog.
+#[derive(Debug)]
+pub enum Verdict {
+    Matches(bool),
+    // Executable did not respond in time.
+    TimedOut,
+    // Executable failed or gave invalid response.
+    Failed(eyre::Report),
+}
+
+/// Returns whether executable of |condition_info| classifies |dialog| as
+/// belonging to rule |rule_name|. Fails only if |dialog| can't be described,
+/// e.g. its messages are not received from Telegram; failures of executable
+/// itself are returned as Verdict::Failed.
+pub async fn classify<D: DialogProperties>(
+    condition_info: &AssignConditionExternalExecutable,
+    dialog: &D,
+    rule_name: &str,
+) -> Result<Verdict> {
+    let min_score = condition_info.min_score.unwrap_or(DEFAULT_MIN_SCORE);
+    let mut state = condition_info.state.0.lock().await;
+    let dialog_key = (dialog.dialog_type(), dialog.id(), dialog.title().to_owned());
+    if let Some((key, response)) = &state.last_response {
+        if *key == dialog_key {
+            return Ok(Verdict::Matches(response.matches(rule_name, min_score)));
+        }
+    }
+    let input = describe(condition_info, dialog).await?;
+    Ok(match ask(condition_info, &mut state, &input).await {
+        Ok(Some(response)) => {
+            let matches = response.matches(rule_name, min_score);
+            state.last_response = Some((dialog_key, response));
+            Verdict::Matches(matches)
+        }
+        Ok(None) => Verdict::TimedOut,
+        Err(e) => Verdict::Failed(e),
+    })
+}
+
+// Returns None if executable does not respond in time.
+async fn ask(
+    condition_info: &AssignConditionExternalExecutable,
+    state: &mut StateData,
+    input: &str,
+) -> Result<Option<Response>> {
+    let output = if condition_info.persistent {
+        let output = run_persistent(condition_info, &mut state.process, input).await;
+        if !matches!(output, Ok(Some(_))) {
+            // Process is killed and restarted for the next dialog, since
+            // its late response would be taken for the next one.
+            state.process = None;
+        }
+        output?
+    } else {
+        run_once(condition_info, input).await?
+    };
+    output.map(|output| Response::parse(&output)).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_is_matched_against_rule() {
+        let folders = Response::parse(r#"{"folders": ["Work", "News"]}"#).unwrap();
+        assert!(folders.matches("News", DEFAULT_MIN_SCORE));
+        assert!(!folders.matches("Family", DEFAULT_MIN_SCORE));
+        let score = Response::parse("{\"score\": 0.7}\n").unwrap();
+        assert!(score.matches("Any", DEFAULT_MIN_SCORE));
+        assert!(!score.matches("Any", 0.9));
+        assert!(!Response::parse(r#"{"match": false}"#)
+            .unwrap()
+            .matches("Any", DEFAULT_MIN_SCORE));
+        assert!(Response::parse(r#"{"match": true, "score": 1}"#).is_err());
+        assert!(Response::parse("{}").is_err());
+        assert!(Response::parse("yes").is_err());
+    }
+
+    struct TestDialog {
+        dialog_type: DialogType,
+        id: i64,
+    }
+
+    impl DialogProperties for TestDialog {
+        fn dialog_type(&self) -> DialogType {
+            self.dialog_type.clone()
+        }
+
+        fn id(&self) -> i64 {
+            self.id
+        }
+
+        fn title(&self) -> &str {
+            "Robots"
+        }
+
+        fn username(&self) -> Option<&str> {
+            None
+        }
+
+        async fn about(&self) -> Result<Option<String>> {
+            Ok(None)
+        }
+
+        async fn has_participant(&self, _login: &str) -> Result<bool> {
+            Ok(false)
+        }
+
+        async fn recent_messages(&self, _limit: usize) -> Result<Vec<String>> {
+            Err(eyre!("Connection lost"))
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn only_executable_failures_are_verdicts() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("tg-tool-verdicts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("classify.sh");
+        // Logs requests, answering only for channels.
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh
+read -r dialog
+echo request >> {}
+                 case \"$dialog\" in
+                 *'\"type\":\"channel\"'*) echo '{{\"match\": true}}' ;;
+                 *) echo maybe ;;
+                 esac
+",
+                dir.join("log").display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let condition = |include_messages| AssignConditionExternalExecutable {
+            path: script.display().to_string(),
+            include_messages,
+            ..Default::default()
+        };
+        let channel = TestDialog {
+            dialog_type: DialogType::Channel,
+            id: 100,
+        };
+        let user = TestDialog {
+            dialog_type: DialogType::User,
+            id: 100,
+        };
+
+        let error = classify(&condition(1), &channel, "Robots")
+            .await
+            .unwrap_err();
+        let cached = condition(0);
+        let channel_verdict = classify(&cached, &channel, "Robots").await.unwrap();
+        // User with the same ID and title is not served from cache.
+        let user_verdict = classify(&cached, &user, "Robots").await.unwrap();
+        let log = std::fs::read_to_string(dir.join("log")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(error.to_string(), "Connection lost");
+        assert!(matches!(channel_verdict, Verdict::Matches(true)));
+        let Verdict::Failed(e) = user_verdict else {
+            panic!("Invalid response must fail executable, got {user_verdict:?}");
+        };
+        assert!(e.to_string().contains("Invalid response"));
+        assert_eq!(log, "request\nrequest\n");
+    }
+}
@@ -0,0 +1,291 @@
+use super::classifier::{classify, Verdict};
+use super::executable;
+use super::{
+    is_placeholder, AssignCondition, AssignConditionComposite, AssignConditionContactPresent,
+    AssignConditionDialogType, AssignConditionExternalExecutable, AssignConditionInfoRegex,
+    AssignConditionTitleRegex, ChatFilter, ChatFilters, DialogType, ExecutableProtocol,
+    TimeoutAction,
+};
+use eyre::{eyre, Result};
+use log::{error, warn};
+
+/// Dialog as seen by rules conditions. Implemented both for real Telegram
+/// dialogs and for fake dialogs from test fixtures. Like TelegramBackend,
+/// does not require returned futures to be Send.
+#[allow(async_fn_in_trait)]
+pub trait DialogProperties {
+    fn dialog_type(&self) -> DialogType;
+    fn id(&self) -> i64;
+    fn title(&self) -> &str;
+    fn username(&self) -> Option<&str>;
+    /// Returns all usernames, the main one first.
+    fn usernames(&self) -> Vec<&str> {
+        self.username().into_iter().collect()
+    }
+    /// Returns properties like "bot", "verified" or "scam", passed to
+    /// external classifiers.
+    fn flags(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// Returns None for dialogs without description, e.g. with users.
+    async fn about(&self) -> Result<Option<String>>;
+    async fn has_participant(&self, login: &str) -> Result<bool>;
+    /// Returns usernames of participants having them. Needed only by
+    /// external classifiers with "include_participants".
+    async fn participants(&self) -> Result<Vec<String>> {
+        Err(eyre!(
+            "Listing participants is not supported for this dialog"
+        ))
+    }
+    /// Returns texts of up to |limit| latest messages, newest first. Needed
+    /// only by external classifiers with "include_messages".
+    async fn recent_messages(&self, limit: usize) -> Result<Vec<String>> {
+        let _ = limit;
+        Err(eyre!("Reading messages is not supported for this dialog"))
+    }
+}
+
+fn chat_title_match<D: DialogProperties>(
+    regex_info: &AssignConditionTitleRegex,
+    dialog: &D,
+) -> Result<bool> {
+    regex_info.regex_match.is_match(dialog.title())
+}
+
+async fn chat_info_match<D: DialogProperties>(
+    regex_info: &AssignConditionInfoRegex,
+    dialog: &D,
+) -> Result<bool> {
+    match dialog.about().await {
+        Ok(Some(about)) => regex_info.regex_match.is_match(&about),
+        // Most probably this is dialog with user, not chat.
+        Ok(None) => Ok(false),
+        Err(e) => {
+            error!(
+                "Error {e:?} during ChatFullInfo fetching on dialog {}.",
+                dialog.title()
+            );
+            Ok(false)
+        }
+    }
+}
+
+async fn chat_contact_present<D: DialogProperties>(
+    contact_info: &AssignConditionContactPresent,
+    dialog: &D,
+) -> bool {
+    match dialog.has_participant(&contact_info.login).await {
+        Ok(present) => present,
+        Err(e) => {
+            warn!(
+                "Failed to enumerate participants on chat {} error {}",
+                dialog.title(),
+                e
+            );
+            false
+        }
+    }
+}
+
+fn chat_dialog_type_match<D: DialogProperties>(
+    dialog_type_info: &AssignConditionDialogType,
+    dialog: &D,
+) -> bool {
+    dialog_type_info.dialog_type == dialog.dialog_type()
+}
+
+/// Rule being checked, for conditions depending on it.
+#[derive(Clone, Copy)]
+struct MatchContext<'a> {
+    rule_name: &'a str,
+    // Whether dialog has matched any of the previous rules, used by
+    // not_matched condition.
+    has_matched_filters: bool,
+}
+
+async fn chat_and_conditions<D: DialogProperties>(
+    condition_info: &AssignConditionComposite,
+    dialog: &D,
+    context: MatchContext<'_>,
+) -> Result<bool> {
+    for child in &condition_info.children {
+        if !Box::pin(condition_match(child, dialog, context)).await? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+async fn chat_or_conditions<D: DialogProperties>(
+    condition_info: &AssignConditionComposite,
+    dialog: &D,
+    context: MatchContext<'_>,
+) -> Result<bool> {
+    for child in &condition_info.children {
+        if Box::pin(condition_match(child, dialog, context)).await? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn placeholder_value<D: DialogProperties>(placeholder: &str, dialog: &D) -> Option<String> {
+    let dialog_type = dialog.dialog_type();
+    let login = || dialog.username().map(|s| s.to_owned());
+    match placeholder {
+        "@id@" => Some(dialog.id().to_string()),
+        "@user_login@" if dialog_type == DialogType::User => login(),
+        "@group_login@" if dialog_type == DialogType::Group => login(),
+        "@group_title@" if dialog_type == DialogType::Group => Some(dialog.title().to_owned()),
+        "@channel_login@" if dialog_type == DialogType::Channel => login(),
+        "@channel_title@" if dialog_type == DialogType::Channel => Some(dialog.title().to_owned()),
+        "@user_login@" | "@group_login@" | "@group_title@" | "@channel_login@"
+        | "@channel_title@" => None,
+        _ => {
+            error!("Unknown placeholder {placeholder}");
+            None
+        }
+    }
+}
+
+// Returns result of condition, which executable did not finish in time.
+fn chat_external_executable_timeout<D: DialogProperties>(
+    condition_info: &AssignConditionExternalExecutable,
+    dialog: &D,
+) -> Result<bool> {
+    let timeout_ms = executable::timeout_ms(condition_info);
+    match condition_info.on_timeout {
+        TimeoutAction::Error => Err(eyre!(
+            "{:?} did not finish in {timeout_ms} ms on dialog {}",
+            condition_info.path,
+            dialog.title()
+        )),
+        action => {
+            warn!(
+                "{:?} did not finish in {timeout_ms} ms on dialog {}, considered {}",
+                condition_info.path,
+                dialog.title(),
+                if action == TimeoutAction::Match {
+                    "matching"
+                } else {
+                    "not matching"
+                }
+            );
+            Ok(action == TimeoutAction::Match)
+        }
+    }
+}
+
+async fn chat_external_executable_check<D: DialogProperties>(
+    condition_info: &AssignConditionExternalExecutable,
+    dialog: &D,
+) -> Result<bool> {
+    let mut resolved_params = Vec::new();
+    for param in &condition_info.params {
+        if is_placeholder(param) {
+            if let Some(value) = placeholder_value(param, dialog) {
+                resolved_params.push(value)
+            } else {
+                return Ok(false);
+            }
+        } else {
+            resolved_params.push(param.to_owned());
+        }
+    }
+    let output = executable::run(
+        condition_info,
+        &resolved_params,
+        condition_info.stdin.as_deref(),
+    )
+    .await;
+    match output {
+        Err(e) => {
+            error!("Failed execute {:?}; error {}", condition_info.path, e);
+            Ok(false)
+        }
+        Ok(Some(output)) => Ok(output.status.success()),
+        Ok(None) => chat_external_executable_timeout(condition_info, dialog),
+    }
+}
+
+async fn chat_external_classifier_check<D: DialogProperties>(
+    condition_info: &AssignConditionExternalExecutable,
+    dialog: &D,
+    rule_name: &str,
+) -> Result<bool> {
+    // Errors of describing dialog come from Telegram and fail the run,
+    // like ones of other conditions.
+    match classify(condition_info, dialog, rule_name).await? {
+        Verdict::Matches(matches) => Ok(matches),
+        Verdict::TimedOut => chat_external_executable_timeout(condition_info, dialog),
+        Verdict::Failed(e) => {
+            error!(
+                "Failed classify dialog {} by {:?}; error {e}",
+                dialog.title(),
+                condition_info.path
+            );
+            Ok(false)
+        }
+    }
+}
+
+async fn condition_match<D: DialogProperties>(
+    condition: &AssignCondition,
+    dialog: &D,
+    context: MatchContext<'_>,
+) -> Result<bool> {
+    match condition {
+        AssignCondition::TitleRegex(condition_info) => chat_title_match(condition_info, dialog),
+        AssignCondition::InfoRegex(condition_info) => chat_info_match(condition_info, dialog).await,
+        AssignCondition::ContactPresent(condition_info) => {
+            Ok(chat_contact_present(condition_info, dialog).await)
+        }
+        AssignCondition::DialogType(condition_info) => {
+            Ok(chat_dialog_type_match(condition_info, dialog))
+        }
+        AssignCondition::And(condition_info) => {
+            chat_and_conditions(condition_info, dialog, context).await
+        }
+        AssignCondition::Or(condition_info) => {
+            chat_or_conditions(condition_info, dialog, context).await
+        }
+        AssignCondition::Not(child_condition) => {
+            Ok(!Box::pin(condition_match(child_condition, dialog, context)).await?)
+        }
+        AssignCondition::ExternalExecutable(condition_info) => match condition_info.protocol {
+            ExecutableProtocol::ExitCode => {
+                chat_external_executable_check(condition_info, dialog).await
+            }
+            ExecutableProtocol::Json => {
+                chat_external_classifier_check(condition_info, dialog, context.rule_name).await
+            }
+        },
+        // References are resolved by load_rules, but rules may be
+        // deserialized by library users directly.
+        AssignCondition::Ref(name) => Err(eyre!(
+            "Reference to definition \"{name}\" is not resolved, rules must be loaded by load_rules"
+        )),
+        AssignCondition::NotMatched => Ok(!context.has_matched_filters),
+    }
+}
+
+/// Returns rules matching |dialog|, in order of rules file. Fails if
+/// external executable with "on_timeout": "error" does not finish in time,
+/// or if rules were not loaded by load_rules and have unresolved references
+/// or variables.
+pub async fn apply_rules<'a, D: DialogProperties>(
+    filters: &'a ChatFilters,
+    dialog: &D,
+) -> Result<Vec<&'a ChatFilter>> {
+    let mut result = Vec::new();
+    for filter in filters {
+        let context = MatchContext {
+            rule_name: &filter.name,
+            has_matched_filters: !result.is_empty(),
+        };
+        if condition_match(&filter.condition, dialog, context).await? {
+            result.push(filter);
+        }
+    }
+    Ok(result)
+}
@@ -0,0 +1,111 @@
+//! Running of external_executable programs with environment, working
+//! directory and time limit of the condition. Programs never block the
+//! runtime, and their stderr is captured and logged.
+
+use super::AssignConditionExternalExecutable;
+use eyre::Result;
+use log::{debug, warn};
+use std::future::Future;
+use std::{process, time};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+// Used when condition does not set "timeout_ms", so hanging executable does
+// not stall the whole run.
+const DEFAULT_TIMEOUT_MS: u64 = 60_000;
+
+/// Returns time limit of executable run in milliseconds.
+pub fn timeout_ms(condition_info: &AssignConditionExternalExecutable) -> u64 {
+    condition_info.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS)
+}
+
+/// Returns command starting executable of |condition_info|, with stdout and
+/// stderr piped, and stdin closed unless piped by caller.
+pub fn command(condition_info: &AssignConditionExternalExecutable) -> tokio::process::Command {
+    let path = shellexpand::tilde(&condition_info.path).into_owned();
+    let mut command = tokio::process::Command::new(path);
+    if condition_info.clear_env {
+        command.env_clear();
+    }
+    command.envs(&condition_info.env);
+    if let Some(cwd) = &condition_info.cwd {
+        command.current_dir(shellexpand::tilde(cwd).as_ref());
+    }
+    command
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .kill_on_drop(true);
+    command
+}
+
+/// Awaits |future| within time limit of |condition_info|. Returns None if
+/// it expires.
+pub async fn with_timeout<T>(
+    condition_info: &AssignConditionExternalExecutable,
+    future: impl Future<Output = T>,
+) -> Option<T> {
+    let timeout = time::Duration::from_millis(timeout_ms(condition_info));
+    tokio::time::timeout(timeout, future).await.ok()
+}
+
+// Logs lines of |stderr| as they come, so they are not lost when executable
+// is killed.
+async fn log_stderr(path: &str, stderr: tokio::process::ChildStderr) {
+    let mut lines = tokio::io::BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if !line.trim().is_empty() {
+            warn!("{path}: {}", line.trim_end());
+        }
+    }
+}
+
+/// Logs stderr of persistent process in background.
+pub fn forward_stderr(path: &str, stderr: tokio::process::ChildStderr) {
+    let path = path.to_owned();
+    tokio::spawn(async move { log_stderr(&path, stderr).await });
+}
+
+/// Runs executable of |condition_info| with |args|, writing |input| to its
+/// stdin. Returns its exit status and stdout, or None if it does not finish
+/// in time and is killed.
+pub async fn run(
+    condition_info: &AssignConditionExternalExecutable,
+    args: &[String],
+    input: Option<&str>,
+) -> Result<Option<process::Output>> {
+    let mut command = command(condition_info);
+    command.args(args);
+    if input.is_some() {
+        command.stdin(process::Stdio::piped());
+    }
+    let mut child = command.spawn()?;
+    let stdin = child.stdin.take();
+    let stderr = child.stderr.take().expect("stderr is piped");
+    // Input is written while output is read, so executable writing output
+    // before reading the whole input does not hang.
+    let write_input = async move {
+        if let (Some(mut stdin), Some(input)) = (stdin, input) {
+            // Executable may exit without reading input.
+            let _ = stdin.write_all(input.as_bytes()).await;
+        }
+        // Closed stdin tells executable that the whole input is written.
+    };
+    let run = async {
+        tokio::join!(
+            write_input,
+            log_stderr(&condition_info.path, stderr),
+            child.wait_with_output()
+        )
+        .2
+    };
+    // Child is killed on timeout, when dropped with the future.
+    let Some(output) = with_timeout(condition_info, run).await else {
+        return Ok(None);
+    };
+    let output = output?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !stdout.trim().is_empty() {
+        debug!("{} printed {:?}", condition_info.path, stdout.trim_end());
+    }
+    Ok(Some(output))
+}
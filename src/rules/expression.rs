@@ -0,0 +1,607 @@
+// Compact textual syntax for conditions, e.g.
+//   type == Channel && !matched && title ~ /(?i)robo/
+//
+// Grammar:
+//   expr       := and_expr ( "||" and_expr )*
+//   and_expr   := unary ( "&&" unary )*
+//   unary      := "!" unary | primary
+//   primary    := "(" expr ")"
+//               | "matched"
+//               | "type" ( "==" | "!=" ) ( "User" | "Group" | "Channel" )
+//               | ( "title" | "about" ) ( "~" | "!~" ) ( REGEX | STRING )
+//               | "contact" ( "==" | "!=" ) STRING
+//               | "exec" "(" STRING ( "," STRING )* ")"
+//               | "ref" "(" IDENT ")"
+//   REGEX      := "/" characters, with "\/" for slash "/"
+//   STRING     := '"' characters, with "\"" and "\\" escapes '"'
+use super::{
+    AssignCondition, AssignConditionComposite, AssignConditionContactPresent,
+    AssignConditionDialogType, AssignConditionExternalExecutable, AssignConditionInfoRegex,
+    AssignConditionTitleRegex, DialogType, RegexPattern,
+};
+use std::fmt;
+
+#[derive(Debug)]
+pub struct ParseError {
+    // 1-based, in characters.
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(column: usize, message: impl Into<String>) -> Self {
+        Self {
+            column,
+            message: message.into(),
+        }
+    }
+
+    /// Formats error together with |source| and marker under the error
+    /// position.
+    pub fn describe(&self, source: &str) -> String {
+        format!(
+            "{self}\n  {source}\n  {}^",
+            " ".repeat(self.column.saturating_sub(1))
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.column)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Comma,
+    And,
+    Or,
+    Not,
+    Eq,
+    NotEq,
+    Match,
+    NotMatch,
+    Ident(String),
+    Str(String),
+    Regex(String),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::LParen => write!(f, "\"(\""),
+            Token::RParen => write!(f, "\")\""),
+            Token::Comma => write!(f, "\",\""),
+            Token::And => write!(f, "\"&&\""),
+            Token::Or => write!(f, "\"||\""),
+            Token::Not => write!(f, "\"!\""),
+            Token::Eq => write!(f, "\"==\""),
+            Token::NotEq => write!(f, "\"!=\""),
+            Token::Match => write!(f, "\"~\""),
+            Token::NotMatch => write!(f, "\"!~\""),
+            Token::Ident(ident) => write!(f, "\"{ident}\""),
+            Token::Str(_) => write!(f, "string"),
+            Token::Regex(_) => write!(f, "regex"),
+            Token::End => write!(f, "end of expression"),
+        }
+    }
+}
+
+// Reads literal body up to unescaped |terminator|. Escaped terminator and,
+// for strings, escaped backslash are unescaped, other escapes are kept
+// as is, so regex escapes like "\d" work without doubling.
+fn read_literal(
+    chars: &[char],
+    pos: &mut usize,
+    terminator: char,
+    unescape_backslash: bool,
+) -> Option<String> {
+    let mut result = String::new();
+    while *pos < chars.len() {
+        let c = chars[*pos];
+        *pos += 1;
+        if c == terminator {
+            return Some(result);
+        }
+        if c == '\\' && *pos < chars.len() {
+            let next = chars[*pos];
+            if next == terminator || (unescape_backslash && next == '\\') {
+                result.push(next);
+                *pos += 1;
+                continue;
+            }
+        }
+        result.push(c);
+    }
+    None
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut result = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        let column = pos + 1;
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+        let next = chars.get(pos + 1).copied();
+        let (token, len) = match (c, next) {
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            (',', _) => (Token::Comma, 1),
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Eq, 2),
+            ('!', Some('=')) => (Token::NotEq, 2),
+            ('!', Some('~')) => (Token::NotMatch, 2),
+            ('!', _) => (Token::Not, 1),
+            ('~', _) => (Token::Match, 1),
+            ('"', _) | ('/', _) => {
+                pos += 1;
+                let literal = read_literal(&chars, &mut pos, c, c == '"').ok_or_else(|| {
+                    let kind = if c == '"' { "string" } else { "regex" };
+                    ParseError::new(column, format!("Unterminated {kind}"))
+                })?;
+                let token = if c == '"' {
+                    Token::Str(literal)
+                } else {
+                    Token::Regex(literal)
+                };
+                result.push((token, column));
+                continue;
+            }
+            (c, _) if c.is_alphanumeric() || c == '_' => {
+                let len = chars[pos..]
+                    .iter()
+                    .take_while(|c| c.is_alphanumeric() || **c == '_')
+                    .count();
+                let ident = chars[pos..pos + len].iter().collect();
+                (Token::Ident(ident), len)
+            }
+            _ => {
+                return Err(ParseError::new(
+                    column,
+                    format!("Unexpected character '{c}'"),
+                ));
+            }
+        };
+        result.push((token, column));
+        pos += len;
+    }
+    result.push((Token::End, chars.len() + 1));
+    Ok(result)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn column(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn advance(&mut self) -> (Token, usize) {
+        let result = std::mem::replace(&mut self.tokens[self.pos], (Token::End, 0));
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        result
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        ParseError::new(
+            self.column(),
+            format!("Expected {expected}, found {}", self.peek()),
+        )
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), ParseError> {
+        if *self.peek() != token {
+            return Err(self.unexpected(&token.to_string()));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_string(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Token::Str(_) => match self.advance() {
+                (Token::Str(s), _) => Ok(s),
+                _ => unreachable!(),
+            },
+            _ => Err(self.unexpected("string")),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<AssignCondition, ParseError> {
+        let mut children = vec![self.parse_and()?];
+        while *self.peek() == Token::Or {
+            self.advance();
+            children.push(self.parse_and()?);
+        }
+        Ok(composite(children, AssignCondition::Or))
+    }
+
+    fn parse_and(&mut self) -> Result<AssignCondition, ParseError> {
+        let mut children = vec![self.parse_unary()?];
+        while *self.peek() == Token::And {
+            self.advance();
+            children.push(self.parse_unary()?);
+        }
+        Ok(composite(children, AssignCondition::And))
+    }
+
+    fn parse_unary(&mut self) -> Result<AssignCondition, ParseError> {
+        if *self.peek() == Token::Not {
+            self.advance();
+            let child = self.parse_unary()?;
+            return Ok(negate(child));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<AssignCondition, ParseError> {
+        if *self.peek() == Token::LParen {
+            self.advance();
+            let result = self.parse_or()?;
+            self.expect(Token::RParen)?;
+            return Ok(result);
+        }
+        let ident = match self.peek() {
+            Token::Ident(ident) => ident.clone(),
+            _ => {
+                return Err(self.unexpected("condition"));
+            }
+        };
+        let ident_column = self.column();
+        self.advance();
+        match ident.as_str() {
+            "matched" => Ok(AssignCondition::Not(Box::new(AssignCondition::NotMatched))),
+            "type" => self.parse_dialog_type(),
+            "title" => self.parse_regex_match(|regex_match| {
+                AssignCondition::TitleRegex(AssignConditionTitleRegex { regex_match })
+            }),
+            "about" => self.parse_regex_match(|regex_match| {
+                AssignCondition::InfoRegex(AssignConditionInfoRegex { regex_match })
+            }),
+            "contact" => {
+                let negate = self.parse_equality()?;
+                let login = self.expect_string()?;
+                let result =
+                    AssignCondition::ContactPresent(AssignConditionContactPresent { login });
+                Ok(negate_if(negate, result))
+            }
+            "exec" => self.parse_exec(),
+            "ref" => {
+                self.expect(Token::LParen)?;
+                let name = match self.peek() {
+                    Token::Ident(name) => name.clone(),
+                    _ => {
+                        return Err(self.unexpected("definition name"));
+                    }
+                };
+                self.advance();
+                self.expect(Token::RParen)?;
+                Ok(AssignCondition::Ref(name))
+            }
+            _ => Err(ParseError::new(
+                ident_column,
+                format!(
+                    "Unknown condition \"{ident}\", expected one of \
+                     matched, type, title, about, contact, exec, ref"
+                ),
+            )),
+        }
+    }
+
+    // Returns true for "!=".
+    fn parse_equality(&mut self) -> Result<bool, ParseError> {
+        let negate = match self.peek() {
+            Token::Eq => false,
+            Token::NotEq => true,
+            _ => {
+                return Err(self.unexpected("\"==\" or \"!=\""));
+            }
+        };
+        self.advance();
+        Ok(negate)
+    }
+
+    fn parse_dialog_type(&mut self) -> Result<AssignCondition, ParseError> {
+        let negate = self.parse_equality()?;
+        let dialog_type = match self.peek() {
+            Token::Ident(ident) => match ident.to_lowercase().as_str() {
+                "user" => DialogType::User,
+                "group" => DialogType::Group,
+                "channel" => DialogType::Channel,
+                _ => {
+                    return Err(self.unexpected("User, Group or Channel"));
+                }
+            },
+            _ => {
+                return Err(self.unexpected("User, Group or Channel"));
+            }
+        };
+        self.advance();
+        let result = AssignCondition::DialogType(AssignConditionDialogType { dialog_type });
+        Ok(negate_if(negate, result))
+    }
+
+    fn parse_regex_match<F>(&mut self, make_condition: F) -> Result<AssignCondition, ParseError>
+    where
+        F: FnOnce(RegexPattern) -> AssignCondition,
+    {
+        let negate = match self.peek() {
+            Token::Match => false,
+            Token::NotMatch => true,
+            _ => {
+                return Err(self.unexpected("\"~\" or \"!~\""));
+            }
+        };
+        self.advance();
+        let (pattern, column) = match self.peek() {
+            Token::Regex(_) | Token::Str(_) => match self.advance() {
+                (Token::Regex(pattern), column) | (Token::Str(pattern), column) => {
+                    (pattern, column)
+                }
+                _ => unreachable!(),
+            },
+            _ => {
+                return Err(self.unexpected("regex"));
+            }
+        };
+        let regex = RegexPattern::new(pattern).map_err(|e| {
+            // Syntax errors are multi-line with pattern repeated, keep only
+            // the description.
+            let message = e.to_string();
+            let description = message.lines().last().unwrap_or_default().to_owned();
+            ParseError::new(column, format!("Invalid regex; {description}"))
+        })?;
+        Ok(negate_if(negate, make_condition(regex)))
+    }
+
+    fn parse_exec(&mut self) -> Result<AssignCondition, ParseError> {
+        self.expect(Token::LParen)?;
+        let path = self.expect_string()?;
+        let mut params = Vec::new();
+        while *self.peek() == Token::Comma {
+            self.advance();
+            params.push(self.expect_string()?);
+        }
+        self.expect(Token::RParen)?;
+        Ok(AssignCondition::ExternalExecutable(
+            AssignConditionExternalExecutable {
+                path,
+                params,
+                ..Default::default()
+            },
+        ))
+    }
+}
+
+fn composite<F>(mut children: Vec<AssignCondition>, make: F) -> AssignCondition
+where
+    F: FnOnce(AssignConditionComposite) -> AssignCondition,
+{
+    if children.len() == 1 {
+        return children.pop().unwrap();
+    }
+    make(AssignConditionComposite { children })
+}
+
+fn negate(condition: AssignCondition) -> AssignCondition {
+    match condition {
+        AssignCondition::Not(child) => *child,
+        _ => AssignCondition::Not(Box::new(condition)),
+    }
+}
+
+fn negate_if(need_negate: bool, condition: AssignCondition) -> AssignCondition {
+    if need_negate {
+        negate(condition)
+    } else {
+        condition
+    }
+}
+
+pub fn parse(source: &str) -> Result<AssignCondition, ParseError> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let result = parser.parse_or()?;
+    if *parser.peek() != Token::End {
+        return Err(parser.unexpected("\"&&\", \"||\" or end of expression"));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Compact description of parsed condition, for comparing with expected.
+    fn show(condition: &AssignCondition) -> String {
+        let join =
+            |children: &[AssignCondition]| children.iter().map(show).collect::<Vec<_>>().join(", ");
+        match condition {
+            AssignCondition::And(c) => format!("and({})", join(&c.children)),
+            AssignCondition::Or(c) => format!("or({})", join(&c.children)),
+            AssignCondition::Not(child) => format!("not({})", show(child)),
+            AssignCondition::TitleRegex(c) => format!("title(/{}/)", c.regex_match.source),
+            AssignCondition::InfoRegex(c) => format!("about(/{}/)", c.regex_match.source),
+            AssignCondition::DialogType(c) => {
+                let dialog_type = match c.dialog_type {
+                    DialogType::User => "User",
+                    DialogType::Group => "Group",
+                    DialogType::Channel => "Channel",
+                };
+                format!("type({dialog_type})")
+            }
+            AssignCondition::ContactPresent(c) => format!("contact({})", c.login),
+            AssignCondition::ExternalExecutable(c) => {
+                let mut args = vec![c.path.clone()];
+                args.extend(c.params.iter().cloned());
+                format!("exec({})", args.join(", "))
+            }
+            AssignCondition::Ref(name) => format!("ref({name})"),
+            AssignCondition::NotMatched => "not_matched".to_owned(),
+        }
+    }
+
+    fn parsed(source: &str) -> String {
+        show(&parse(source).unwrap())
+    }
+
+    fn error(source: &str) -> String {
+        parse(source).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn operators_have_precedence() {
+        assert_eq!(
+            parsed("!type == User && title ~ /a/ || matched"),
+            "or(and(not(type(User)), title(/a/)), not(not_matched))"
+        );
+        assert_eq!(
+            parsed("matched || type == Group && !about ~ /b/"),
+            "or(not(not_matched), and(type(Group), not(about(/b/))))"
+        );
+        assert_eq!(
+            parsed("matched && matched && matched"),
+            "and(not(not_matched), not(not_matched), not(not_matched))"
+        );
+    }
+
+    #[test]
+    fn parentheses_group_conditions() {
+        assert_eq!(
+            parsed("type == User && (title ~ /a/ || about ~ /b/)"),
+            "and(type(User), or(title(/a/), about(/b/)))"
+        );
+        assert_eq!(
+            parsed("!(matched || ((ref(x))))"),
+            "not(or(not(not_matched), ref(x)))"
+        );
+    }
+
+    #[test]
+    fn double_negation_is_removed() {
+        assert_eq!(parsed("!matched"), "not_matched");
+        assert_eq!(parsed("!!matched"), "not(not_matched)");
+        assert_eq!(parsed("!(type != Channel)"), "type(Channel)");
+    }
+
+    #[test]
+    fn all_terms_are_parsed() {
+        let cases = [
+            ("matched", "not(not_matched)"),
+            ("type == User", "type(User)"),
+            ("type != group", "not(type(Group))"),
+            ("type==Channel", "type(Channel)"),
+            ("title ~ /^Robo/", "title(/^Robo/)"),
+            ("title !~ \"news\"", "not(title(/news/))"),
+            ("about ~ /(?i)shop/", "about(/(?i)shop/)"),
+            ("about !~ /x/", "not(about(/x/))"),
+            ("contact == \"bob\"", "contact(bob)"),
+            ("contact != \"bob\"", "not(contact(bob))"),
+            ("exec(\"~/check\")", "exec(~/check)"),
+            (
+                "exec(\"/bin/check\", \"@id@\", \"x y\")",
+                "exec(/bin/check, @id@, x y)",
+            ),
+            ("ref(work_chats)", "ref(work_chats)"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parsed(source), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn literals_are_unescaped() {
+        assert_eq!(parsed(r"title ~ /a\/b\d/"), r"title(/a/b\d/)");
+        assert_eq!(
+            parsed(r#"title ~ "say \"hi\" \\ \d""#),
+            r#"title(/say "hi" \ \d/)"#
+        );
+        assert_eq!(parsed(r#"contact == "a\"b""#), r#"contact(a"b)"#);
+    }
+
+    #[test]
+    fn errors_have_columns() {
+        let cases = [
+            ("type = User", "Unexpected character '=' at column 6"),
+            ("title ~ /abc", "Unterminated regex at column 9"),
+            ("contact == \"bob", "Unterminated string at column 12"),
+            (
+                "type == Bot",
+                "Expected User, Group or Channel, found \"Bot\" at column 9",
+            ),
+            (
+                "foo",
+                "Unknown condition \"foo\", expected one of \
+                 matched, type, title, about, contact, exec, ref at column 1",
+            ),
+            (
+                "(matched",
+                "Expected \")\", found end of expression at column 9",
+            ),
+            (
+                "matched matched",
+                "Expected \"&&\", \"||\" or end of expression, found \"matched\" at column 9",
+            ),
+            (
+                "title == /a/",
+                "Expected \"~\" or \"!~\", found \"==\" at column 7",
+            ),
+            ("exec(\"a\", )", "Expected string, found \")\" at column 11"),
+            (
+                "ref(\"x\")",
+                "Expected definition name, found string at column 5",
+            ),
+            (
+                "matched &&",
+                "Expected condition, found end of expression at column 11",
+            ),
+            (
+                "",
+                "Expected condition, found end of expression at column 1",
+            ),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(error(source), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn invalid_regex_is_reported_at_its_column() {
+        let message = error("type == User && title ~ /(/");
+        assert!(message.starts_with("Invalid regex; "), "{message}");
+        assert!(message.ends_with(" at column 25"), "{message}");
+    }
+
+    #[test]
+    fn columns_are_counted_in_characters() {
+        assert_eq!(
+            error("title ~ \"ф\" && foo"),
+            "Unknown condition \"foo\", expected one of \
+             matched, type, title, about, contact, exec, ref at column 16"
+        );
+        let error = parse("type = User").err().unwrap();
+        assert_eq!(
+            error.describe("type = User"),
+            "Unexpected character '=' at column 6\n  type = User\n       ^"
+        );
+    }
+}
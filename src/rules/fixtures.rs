@@ -0,0 +1,125 @@
+use super::engine::DialogProperties;
+use super::DialogType;
+use crate::formats;
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use std::path;
+
+// Fixtures file is a table with "dialogs" key, so it can be written in TOML
+// as well.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixturesFile {
+    dialogs: Vec<FixtureDialog>,
+}
+
+/// Fake dialog for testing rules without connecting to Telegram.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixtureDialog {
+    #[serde(rename = "type")]
+    pub dialog_type: DialogType,
+    #[serde(default)]
+    pub id: i64,
+    pub title: String,
+    #[serde(default)]
+    pub about: Option<String>,
+    // First username is the main one, used for placeholders.
+    #[serde(default)]
+    pub usernames: Vec<String>,
+    // Flags passed to external classifiers, e.g. "bot" or "verified".
+    #[serde(default)]
+    pub flags: Vec<String>,
+    // Usernames of participants.
+    #[serde(default)]
+    pub participants: Vec<String>,
+    // Message texts, newest first.
+    #[serde(default)]
+    pub messages: Vec<String>,
+    // Folders dialog is expected to be assigned to. If absent, assigned
+    // folders are only reported.
+    #[serde(default)]
+    pub expected_folders: Option<Vec<String>>,
+}
+
+impl DialogProperties for FixtureDialog {
+    fn dialog_type(&self) -> DialogType {
+        self.dialog_type.clone()
+    }
+
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn title(&self) -> &str {
+        &self.title
+    }
+
+    fn username(&self) -> Option<&str> {
+        self.usernames.first().map(|s| s.as_str())
+    }
+
+    fn usernames(&self) -> Vec<&str> {
+        self.usernames.iter().map(|s| s.as_str()).collect()
+    }
+
+    fn flags(&self) -> Vec<String> {
+        self.flags.clone()
+    }
+
+    async fn about(&self) -> Result<Option<String>> {
+        if self.dialog_type == DialogType::User {
+            return Ok(None);
+        }
+        Ok(Some(self.about.clone().unwrap_or_default()))
+    }
+
+    async fn has_participant(&self, login: &str) -> Result<bool> {
+        Ok(self.participants.iter().any(|p| p == login))
+    }
+
+    async fn participants(&self) -> Result<Vec<String>> {
+        Ok(self.participants.clone())
+    }
+
+    async fn recent_messages(&self, limit: usize) -> Result<Vec<String>> {
+        Ok(self.messages.iter().take(limit).cloned().collect())
+    }
+}
+
+pub fn load_fixtures(
+    fixtures_file_path: &path::Path,
+    format: Option<formats::FileFormat>,
+) -> Result<Vec<FixtureDialog>> {
+    let format = formats::FileFormat::detect(format, fixtures_file_path);
+    let file: FixturesFile = format.read(fixtures_file_path).map_err(|e| {
+        eyre!(
+            "Failed parse fixtures file {}; {e}",
+            fixtures_file_path.display()
+        )
+    })?;
+    Ok(file.dialogs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixtures_describe_all_dialog_properties() {
+        let file: FixturesFile = serde_yaml::from_str(
+            "dialogs:\n\
+             - {type: User, id: 5, title: Helper, usernames: [helper_bot], flags: [bot, verified]}\n\
+             - {type: Group, title: Family, participants: [mom], expected_folders: [Family]}\n",
+        )
+        .unwrap();
+        let helper = &file.dialogs[0];
+        assert_eq!(helper.username(), Some("helper_bot"));
+        assert_eq!(helper.flags(), vec!["bot", "verified"]);
+        assert!(file.dialogs[1].flags().is_empty());
+        assert!(serde_yaml::from_str::<FixturesFile>(
+            "dialogs:\n- {type: User, title: Helper, flag: bot}\n"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,406 @@
+use super::{is_placeholder, AssignCondition, ChatFilters, ExecutableProtocol, PLACEHOLDERS};
+use crate::utils;
+use serde::Serialize;
+use std::collections;
+use std::env;
+use std::fmt;
+use std::path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Serialize)]
+pub struct Problem {
+    pub severity: Severity,
+    pub rule: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Rule \"{}\": {}", self.rule, self.message)
+    }
+}
+
+fn for_each_condition<F>(condition: &AssignCondition, f: &mut F)
+where
+    F: FnMut(&AssignCondition),
+{
+    f(condition);
+    match condition {
+        AssignCondition::And(composite) | AssignCondition::Or(composite) => {
+            for child in &composite.children {
+                for_each_condition(child, f);
+            }
+        }
+        AssignCondition::Not(child) => for_each_condition(child, f),
+        _ => {}
+    }
+}
+
+// Conservative estimations: true result means that condition indeed
+// matches (does not match) any dialog, false means "unknown".
+fn always_matches(condition: &AssignCondition) -> bool {
+    match condition {
+        AssignCondition::And(composite) => composite.children.iter().all(always_matches),
+        AssignCondition::Or(composite) => composite.children.iter().any(always_matches),
+        AssignCondition::Not(child) => never_matches(child),
+        _ => false,
+    }
+}
+
+fn never_matches(condition: &AssignCondition) -> bool {
+    match condition {
+        AssignCondition::And(composite) => {
+            let mut dialog_types = composite.children.iter().filter_map(|child| match child {
+                AssignCondition::DialogType(info) => Some(&info.dialog_type),
+                _ => None,
+            });
+            let conflicting_types = match dialog_types.next() {
+                Some(first) => dialog_types.any(|t| t != first),
+                None => false,
+            };
+            conflicting_types || composite.children.iter().any(never_matches)
+        }
+        AssignCondition::Or(composite) => composite.children.iter().all(never_matches),
+        AssignCondition::Not(child) => always_matches(child),
+        _ => false,
+    }
+}
+
+// True if condition may match only dialogs, not matched by previous rules.
+fn requires_not_matched(condition: &AssignCondition) -> bool {
+    match condition {
+        AssignCondition::NotMatched => true,
+        AssignCondition::And(composite) => composite.children.iter().any(requires_not_matched),
+        AssignCondition::Or(composite) => {
+            !composite.children.is_empty() && composite.children.iter().all(requires_not_matched)
+        }
+        _ => false,
+    }
+}
+
+// True if condition matches every dialog, not matched by previous rules.
+fn matches_all_not_matched(condition: &AssignCondition) -> bool {
+    match condition {
+        AssignCondition::NotMatched => true,
+        AssignCondition::And(composite) => composite
+            .children
+            .iter()
+            .all(|child| always_matches(child) || matches_all_not_matched(child)),
+        AssignCondition::Or(composite) => composite.children.iter().any(matches_all_not_matched),
+        _ => always_matches(condition),
+    }
+}
+
+fn find_in_path(name: &str) -> Option<path::PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+fn check_executable(executable_path: &str) -> Option<String> {
+    let expanded = shellexpand::tilde(executable_path);
+    let as_path = path::Path::new(expanded.as_ref());
+    // Same as process::Command, search in PATH only bare names.
+    let found = if as_path.components().count() > 1 {
+        Some(as_path.to_path_buf()).filter(|p| p.is_file())
+    } else {
+        find_in_path(&expanded)
+    };
+    let Some(found) = found else {
+        return Some(format!("executable {executable_path:?} is not found"));
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        match found.metadata() {
+            Ok(metadata) if metadata.permissions().mode() & 0o111 == 0 => {
+                return Some(format!("{} is not executable", found.display()));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                return Some(format!("failed to check {}; {e}", found.display()));
+            }
+        }
+    }
+    None
+}
+
+fn regex_problem(kind: &str, regex: &regex::Regex) -> Option<String> {
+    if regex.is_match("") {
+        Some(format!(
+            "{kind} regex \"{}\" matches empty string, so it matches any dialog",
+            regex.as_str()
+        ))
+    } else {
+        None
+    }
+}
+
+/// Checks rules for problems, which can be found without connecting
+/// to Telegram.
+pub fn lint_rules(rules: &ChatFilters) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut add = |severity, rule: &str, message: String| {
+        problems.push(Problem {
+            severity,
+            rule: rule.to_owned(),
+            message,
+        });
+    };
+    let mut seen_names = collections::HashSet::new();
+    // Name of the rule, after which all dialogs are matched.
+    let mut catch_all_rule: Option<&str> = None;
+    for (idx, rule) in rules.iter().enumerate() {
+        let name = rule.name.as_str();
+        let title_len = utils::folder_title_len(name);
+        if title_len > utils::FOLDER_TITLE_LEN_LIMIT {
+            add(
+                Severity::Error,
+                name,
+                format!(
+                    "folder name has length {title_len}, exceeding Telegram limit of {} \
+                     UTF-16 code units",
+                    utils::FOLDER_TITLE_LEN_LIMIT
+                ),
+            );
+        }
+        if !seen_names.insert(name) {
+            add(
+                Severity::Warning,
+                name,
+                "folder name is used by several rules".to_owned(),
+            );
+        }
+        if never_matches(&rule.condition) {
+            add(
+                Severity::Warning,
+                name,
+                "rule is unreachable, its condition never matches".to_owned(),
+            );
+        } else if let Some(catch_all) = catch_all_rule {
+            if requires_not_matched(&rule.condition) {
+                add(
+                    Severity::Warning,
+                    name,
+                    format!(
+                        "rule is unreachable, it requires not_matched, \
+                         but all dialogs are matched by rule \"{catch_all}\""
+                    ),
+                );
+            }
+        }
+        let mut uses_not_matched = false;
+        for_each_condition(&rule.condition, &mut |condition| match condition {
+            AssignCondition::NotMatched => uses_not_matched = true,
+            AssignCondition::TitleRegex(info) => {
+                let problem = info
+                    .regex_match
+                    .regex()
+                    .ok()
+                    .and_then(|regex| regex_problem("title_regex", regex));
+                if let Some(message) = problem {
+                    add(Severity::Warning, name, message);
+                }
+            }
+            AssignCondition::InfoRegex(info) => {
+                let problem = info
+                    .regex_match
+                    .regex()
+                    .ok()
+                    .and_then(|regex| regex_problem("info_regex", regex));
+                if let Some(message) = problem {
+                    add(Severity::Warning, name, message);
+                }
+            }
+            AssignCondition::ExternalExecutable(info) => {
+                for param in &info.params {
+                    if is_placeholder(param) && !PLACEHOLDERS.contains(&param.as_str()) {
+                        add(
+                            Severity::Error,
+                            name,
+                            format!(
+                                "unknown placeholder {param}, known are {}",
+                                PLACEHOLDERS.join(", ")
+                            ),
+                        );
+                    }
+                }
+                if let Some(message) = check_executable(&info.path) {
+                    add(Severity::Error, name, message);
+                }
+                if let Some(cwd) = &info.cwd {
+                    if !path::Path::new(shellexpand::tilde(cwd).as_ref()).is_dir() {
+                        add(
+                            Severity::Error,
+                            name,
+                            format!("working directory {cwd:?} is not found"),
+                        );
+                    }
+                }
+                if info.timeout_ms == Some(0) {
+                    add(
+                        Severity::Error,
+                        name,
+                        "timeout_ms must be positive".to_owned(),
+                    );
+                }
+                let json_options = info.include_participants
+                    || info.include_messages > 0
+                    || info.min_score.is_some()
+                    || info.persistent;
+                match info.protocol {
+                    ExecutableProtocol::ExitCode if json_options => add(
+                        Severity::Warning,
+                        name,
+                        "include_participants, include_messages, min_score and persistent \
+                         have effect only with json protocol"
+                            .to_owned(),
+                    ),
+                    ExecutableProtocol::Json if info.stdin.is_some() => add(
+                        Severity::Warning,
+                        name,
+                        "stdin is ignored with json protocol, dialog is passed on stdin".to_owned(),
+                    ),
+                    ExecutableProtocol::Json if info.params.iter().any(|p| is_placeholder(p)) => {
+                        add(
+                            Severity::Warning,
+                            name,
+                            "placeholders are not replaced with json protocol, \
+                             dialog is passed on stdin"
+                                .to_owned(),
+                        )
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        });
+        if idx == 0 && uses_not_matched {
+            add(
+                Severity::Warning,
+                name,
+                "not_matched in the first rule is always true, \
+                 since no rules precede it"
+                    .to_owned(),
+            );
+        }
+        if catch_all_rule.is_none() && matches_all_not_matched(&rule.condition) {
+            catch_all_rule = Some(name);
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(rules: &str) -> Vec<(Severity, String, String)> {
+        let rules: ChatFilters = serde_yaml::from_str(rules).unwrap();
+        lint_rules(&rules)
+            .into_iter()
+            .map(|p| (p.severity, p.rule, p.message))
+            .collect()
+    }
+
+    fn warning(rule: &str, message: &str) -> (Severity, String, String) {
+        (Severity::Warning, rule.to_owned(), message.to_owned())
+    }
+
+    const NEVER_MATCHES: &str = "rule is unreachable, its condition never matches";
+
+    #[test]
+    fn good_rules_have_no_problems() {
+        let problems = lint(
+            "- {name: Robo, when: 'type == Channel && title ~ /robo/'}\n\
+             - {name: People, when: '!matched && type == User'}\n\
+             - {name: Rest, when: '!matched'}\n",
+        );
+        assert!(problems.is_empty(), "{problems:?}");
+    }
+
+    #[test]
+    fn conflicting_dialog_types_never_match() {
+        let problems = lint(
+            "- {name: Robo, when: 'title ~ /robo/'}\n\
+             - {name: Both, when: 'type == User && title ~ /x/ && type == Channel'}\n\
+             - {name: Same, when: 'type == User && type == User'}\n",
+        );
+        assert_eq!(problems, vec![warning("Both", NEVER_MATCHES)]);
+    }
+
+    #[test]
+    fn or_of_never_matching_children_never_matches() {
+        let problems = lint(
+            "- {name: Robo, when: 'title ~ /robo/'}\n\
+             - {name: None, when: '(type == User && type == Group) || (type == Channel && type == User)'}\n\
+             - {name: Some, when: '(type == User && type == Group) || type == Channel'}\n",
+        );
+        assert_eq!(problems, vec![warning("None", NEVER_MATCHES)]);
+    }
+
+    #[test]
+    fn rules_after_catch_all_are_unreachable() {
+        let problems = lint(
+            "- {name: Robo, when: 'title ~ /robo/'}\n\
+             - {name: Rest, when: '!matched || type == User'}\n\
+             - {name: People, when: '!matched && type == User'}\n\
+             - {name: Channels, when: 'type == Channel'}\n",
+        );
+        assert_eq!(
+            problems,
+            vec![warning(
+                "People",
+                "rule is unreachable, it requires not_matched, \
+                 but all dialogs are matched by rule \"Rest\""
+            )]
+        );
+    }
+
+    #[test]
+    fn not_matched_in_first_rule_is_reported() {
+        let problems = lint(
+            "- {name: First, when: 'type == User && !matched'}\n\
+             - {name: Second, when: '!matched'}\n",
+        );
+        assert_eq!(
+            problems,
+            vec![warning(
+                "First",
+                "not_matched in the first rule is always true, since no rules precede it"
+            )]
+        );
+    }
+
+    #[test]
+    fn regexes_matching_empty_string_are_reported() {
+        let problems = lint(
+            "- {name: Robo, when: 'title ~ /robo|/ && about ~ /x?/ && title ~ /^$/'}\n\
+             - {name: News, when: 'title ~ /news/'}\n",
+        );
+        assert_eq!(
+            problems,
+            vec![
+                warning(
+                    "Robo",
+                    "title_regex regex \"robo|\" matches empty string, so it matches any dialog"
+                ),
+                warning(
+                    "Robo",
+                    "info_regex regex \"x?\" matches empty string, so it matches any dialog"
+                ),
+                warning(
+                    "Robo",
+                    "title_regex regex \"^$\" matches empty string, so it matches any dialog"
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use super::{AnyRulesFile, AssignCondition, ChatFilters, RulesFile};
+use crate::formats;
+use eyre::{eyre, Result};
+use std::collections;
+use std::path;
+
+// Files being read and files already read, by canonical paths.
+#[derive(Default)]
+struct Includes {
+    stack: Vec<path::PathBuf>,
+    visited: collections::HashSet<path::PathBuf>,
+}
+
+// Reads rules file and all files included by it. Rules of included files
+// go before rules of including file. Variables and definitions of including
+// file override ones with the same names from included files. File already
+// read through other includes is skipped, so its rules are not duplicated.
+fn read_with_includes(
+    rules_file_path: &path::Path,
+    format: Option<formats::FileFormat>,
+    includes: &mut Includes,
+) -> Result<RulesFile> {
+    let canonical_path = rules_file_path
+        .canonicalize()
+        .map_err(|e| eyre!("Failed open rules file {}; {e}", rules_file_path.display()))?;
+    if includes.stack.contains(&canonical_path) {
+        let chain: Vec<String> = includes
+            .stack
+            .iter()
+            .chain(std::iter::once(&canonical_path))
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(eyre!("Include cycle: {}", chain.join(" -> ")));
+    }
+    if !includes.visited.insert(canonical_path.clone()) {
+        return Ok(RulesFile::default());
+    }
+    let format = formats::FileFormat::detect(format, rules_file_path);
+    let AnyRulesFile(own) = format.read(rules_file_path).map_err(|e| {
+        eyre!(
+            "Failed parse rules file {}; {}",
+            rules_file_path.display(),
+            e
+        )
+    })?;
+    if own.include.is_empty() {
+        return Ok(own);
+    }
+    includes.stack.push(canonical_path);
+    let base_dir = rules_file_path.parent().unwrap_or(path::Path::new(""));
+    let mut result = RulesFile::default();
+    for include in &own.include {
+        let include_path = base_dir.join(shellexpand::tilde(include).as_ref());
+        let included = read_with_includes(&include_path, None, includes)?;
+        result.rules.extend(included.rules);
+        result.variables.extend(included.variables);
+        result.definitions.extend(included.definitions);
+    }
+    includes.stack.pop();
+    result.rules.extend(own.rules);
+    result.variables.extend(own.variables);
+    result.definitions.extend(own.definitions);
+    Ok(result)
+}
+
+// Replaces references to definitions with referenced conditions and
+// compiles regexes using variables.
+struct Resolver<'a> {
+    variables: &'a collections::BTreeMap<String, String>,
+    definitions: collections::BTreeMap<&'a str, &'a AssignCondition>,
+    resolved: collections::HashMap<String, AssignCondition>,
+    // Definitions being resolved, for cycle detection.
+    stack: Vec<String>,
+}
+
+impl Resolver<'_> {
+    fn definition(&mut self, name: &str) -> Result<AssignCondition> {
+        if let Some(condition) = self.resolved.get(name) {
+            return Ok(condition.clone());
+        }
+        if self.stack.iter().any(|n| n == name) {
+            let mut chain = self.stack.clone();
+            chain.push(name.to_owned());
+            return Err(eyre!("Cycle in definitions: {}", chain.join(" -> ")));
+        }
+        let mut condition = (*self
+            .definitions
+            .get(name)
+            .ok_or_else(|| eyre!("Reference to unknown definition \"{name}\""))?)
+        .clone();
+        self.stack.push(name.to_owned());
+        self.resolve(&mut condition)?;
+        self.stack.pop();
+        self.resolved.insert(name.to_owned(), condition.clone());
+        Ok(condition)
+    }
+
+    fn resolve(&mut self, condition: &mut AssignCondition) -> Result<()> {
+        match condition {
+            AssignCondition::And(composite) | AssignCondition::Or(composite) => {
+                for child in &mut composite.children {
+                    self.resolve(child)?;
+                }
+            }
+            AssignCondition::Not(child) => self.resolve(child)?,
+            AssignCondition::TitleRegex(condition_info) => condition_info
+                .regex_match
+                .substitute_variables(self.variables)?,
+            AssignCondition::InfoRegex(condition_info) => condition_info
+                .regex_match
+                .substitute_variables(self.variables)?,
+            AssignCondition::Ref(name) => {
+                *condition = self.definition(&name.clone())?;
+            }
+            AssignCondition::DialogType(_)
+            | AssignCondition::ContactPresent(_)
+            | AssignCondition::ExternalExecutable(_)
+            | AssignCondition::NotMatched => {}
+        }
+        Ok(())
+    }
+}
+
+pub fn load_rules(
+    rules_file_path: &path::Path,
+    format: Option<formats::FileFormat>,
+) -> Result<ChatFilters> {
+    let file = read_with_includes(rules_file_path, format, &mut Includes::default())?;
+    let mut resolver = Resolver {
+        variables: &file.variables,
+        definitions: file
+            .definitions
+            .iter()
+            .map(|(name, definition)| (name.as_str(), &definition.0))
+            .collect(),
+        resolved: collections::HashMap::new(),
+        stack: Vec::new(),
+    };
+    // Resolve all definitions, even unused, to report errors in them.
+    let names: Vec<&str> = resolver.definitions.keys().copied().collect();
+    for name in names {
+        resolver
+            .definition(name)
+            .map_err(|e| eyre!("Failed resolve definition \"{name}\"; {e}"))?;
+    }
+    let mut rules = Vec::new();
+    for mut rule in file.rules {
+        resolver
+            .resolve(&mut rule.condition)
+            .map_err(|e| eyre!("Failed resolve rule \"{}\"; {e}", rule.name))?;
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Writes |files| into new directory and loads rules from the first one.
+    fn load(test_name: &str, files: &[(&str, &str)]) -> Result<ChatFilters> {
+        let dir =
+            std::env::temp_dir().join(format!("tg-tool-rules-{test_name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, content) in files {
+            fs::write(dir.join(name), content).unwrap();
+        }
+        let result = load_rules(&dir.join(files[0].0), None);
+        fs::remove_dir_all(&dir).unwrap();
+        result
+    }
+
+    fn names(rules: &ChatFilters) -> Vec<&str> {
+        rules.iter().map(|rule| rule.name.as_str()).collect()
+    }
+
+    fn title_regex(condition: &AssignCondition) -> &regex::Regex {
+        match condition {
+            AssignCondition::TitleRegex(info) => info.regex_match.regex().unwrap(),
+            _ => panic!("title_regex condition expected"),
+        }
+    }
+
+    #[test]
+    fn included_rules_go_first_and_shared_include_is_loaded_once() {
+        let rules = load(
+            "diamond",
+            &[
+                (
+                    "main.yaml",
+                    "include: [b.yaml, c.yaml]\nrules:\n- {name: Main, when: matched}\n",
+                ),
+                (
+                    "b.yaml",
+                    "include: [common.yaml]\nrules:\n- {name: B, when: matched}\n",
+                ),
+                (
+                    "c.yaml",
+                    "include: [common.yaml]\nrules:\n- {name: C, when: matched}\n",
+                ),
+                ("common.yaml", "- {name: Common, when: matched}\n"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(names(&rules), vec!["Common", "B", "C", "Main"]);
+    }
+
+    #[test]
+    fn include_cycle_is_reported() {
+        let error = load(
+            "cycle",
+            &[
+                ("main.yaml", "include: [a.yaml]\n"),
+                ("a.yaml", "include: [b.yaml]\n"),
+                ("b.yaml", "include: [a.yaml]\n"),
+            ],
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.starts_with("Include cycle: "), "{error}");
+        assert!(error.ends_with("a.yaml"), "{error}");
+        assert_eq!(error.matches(" -> ").count(), 3, "{error}");
+    }
+
+    #[test]
+    fn variables_are_substituted_and_overridden_by_including_file() {
+        let rules = load(
+            "variables",
+            &[
+                (
+                    "main.yaml",
+                    "include: [base.yaml]\n\
+                     variables: {company: Acme}\n\
+                     rules:\n\
+                     - {name: Work, when: 'title ~ /${company}|${team_1}/'}\n\
+                     - {name: Prices, when: 'title ~ /[${team}]/'}\n",
+                ),
+                (
+                    "base.yaml",
+                    "variables: {company: Other, team_1: Robo}\nrules: []\n",
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(title_regex(&rules[0].condition).as_str(), "Acme|Robo");
+        // Unknown variable in valid regex is part of character class.
+        assert!(title_regex(&rules[1].condition).is_match("price in $"));
+    }
+
+    #[test]
+    fn unknown_variable_is_reported() {
+        let error = load(
+            "unknown-variable",
+            &[(
+                "main.yaml",
+                "rules:\n- {name: Work, when: 'title ~ /${company}/'}\n",
+            )],
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert_eq!(
+            error,
+            "Failed resolve rule \"Work\"; Unknown variable \"company\" in regex ${company}"
+        );
+    }
+
+    #[test]
+    fn definitions_are_resolved() {
+        let rules = load(
+            "definitions",
+            &[(
+                "main.yaml",
+                "definitions:\n\
+                 \x20 robo: {when: 'title ~ /Robo/'}\n\
+                 \x20 robo_channel: {when: 'type == Channel && ref(robo)'}\n\
+                 rules:\n\
+                 - {name: News, when: 'ref(robo_channel)'}\n\
+                 - {name: Bots, condition: {ref: robo}}\n",
+            )],
+        )
+        .unwrap();
+        match &rules[0].condition {
+            AssignCondition::And(composite) => {
+                assert!(matches!(
+                    composite.children[0],
+                    AssignCondition::DialogType(_)
+                ));
+                assert_eq!(title_regex(&composite.children[1]).as_str(), "Robo");
+            }
+            _ => panic!("and condition expected"),
+        }
+        assert_eq!(title_regex(&rules[1].condition).as_str(), "Robo");
+    }
+
+    #[test]
+    fn definition_errors_are_reported() {
+        let cycle = load(
+            "definition-cycle",
+            &[(
+                "main.yaml",
+                "definitions:\n\
+                 \x20 a: {when: 'ref(b)'}\n\
+                 \x20 b: {when: 'matched && ref(a)'}\n",
+            )],
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert_eq!(
+            cycle,
+            "Failed resolve definition \"a\"; Cycle in definitions: a -> b -> a"
+        );
+        let unknown = load(
+            "unknown-definition",
+            &[("main.yaml", "rules:\n- {name: Work, when: 'ref(work)'}\n")],
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert_eq!(
+            unknown,
+            "Failed resolve rule \"Work\"; Reference to unknown definition \"work\""
+        );
+    }
+}
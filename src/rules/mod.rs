@@ -0,0 +1,375 @@
+use crate::utils;
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use std::collections;
+use std::fmt;
+
+mod classifier;
+mod engine;
+mod executable;
+mod expression;
+mod fixtures;
+mod lint;
+mod loader;
+
+pub use engine::{apply_rules, DialogProperties};
+pub use fixtures::{load_fixtures, FixtureDialog};
+pub use lint::{lint_rules, Problem, Severity};
+pub use loader::load_rules;
+
+// Placeholders supported in "params" of external_executable condition.
+pub const PLACEHOLDERS: [&str; 6] = [
+    "@user_login@",
+    "@group_login@",
+    "@group_title@",
+    "@channel_login@",
+    "@channel_title@",
+    "@id@",
+];
+
+pub type ChatFilters = Vec<ChatFilter>;
+
+// Rules file contains either array of rules, or table with "rules" key.
+// Latter form is required for TOML, which does not allow top-level arrays,
+// and for using includes, variables and definitions.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    // Paths relative to the directory of the file.
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    variables: collections::BTreeMap<String, String>,
+    #[serde(default)]
+    definitions: collections::BTreeMap<String, Definition>,
+    #[serde(default)]
+    rules: ChatFilters,
+}
+
+// Wrapper making RulesFile deserializable from array as well.
+struct AnyRulesFile(RulesFile);
+
+struct RulesFileVisitor;
+
+impl<'de> serde::de::Visitor<'de> for RulesFileVisitor {
+    type Value = AnyRulesFile;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("array of rules or table with \"rules\" key")
+    }
+
+    fn visit_seq<A>(self, seq: A) -> Result<AnyRulesFile, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let rules = ChatFilters::deserialize(serde::de::value::SeqAccessDeserializer::new(seq))?;
+        Ok(AnyRulesFile(RulesFile {
+            rules,
+            ..Default::default()
+        }))
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<AnyRulesFile, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        let file = RulesFile::deserialize(serde::de::value::MapAccessDeserializer::new(map))?;
+        Ok(AnyRulesFile(file))
+    }
+}
+
+impl<'de> Deserialize<'de> for AnyRulesFile {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(RulesFileVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "ChatFilterDef")]
+pub struct ChatFilter {
+    pub name: String,
+    pub condition: AssignCondition,
+}
+
+// Condition may be given either as nested structure in "condition" field,
+// or as expression string in "when" field.
+fn condition_from_fields(
+    owner: &str,
+    condition: Option<AssignCondition>,
+    when: Option<String>,
+) -> Result<AssignCondition, String> {
+    match (condition, when) {
+        (Some(condition), None) => Ok(condition),
+        (None, Some(when)) => expression::parse(&when).map_err(|e| {
+            format!(
+                "Invalid \"when\" expression in {owner}: {}",
+                e.describe(&when)
+            )
+        }),
+        (Some(_), Some(_)) => Err(format!(
+            "{owner} has both \"condition\" and \"when\" fields"
+        )),
+        (None, None) => Err(format!(
+            "{owner} has neither \"condition\" nor \"when\" field"
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatFilterDef {
+    name: String,
+    condition: Option<AssignCondition>,
+    when: Option<String>,
+}
+
+impl TryFrom<ChatFilterDef> for ChatFilter {
+    type Error = String;
+
+    fn try_from(def: ChatFilterDef) -> Result<Self, Self::Error> {
+        let owner = format!("rule \"{}\"", def.name);
+        let condition = condition_from_fields(&owner, def.condition, def.when)?;
+        Ok(Self {
+            name: def.name,
+            condition,
+        })
+    }
+}
+
+// Named condition, which may be referenced from rules and other definitions.
+#[derive(Deserialize)]
+#[serde(try_from = "DefinitionDef")]
+struct Definition(AssignCondition);
+
+#[derive(Deserialize)]
+struct DefinitionDef {
+    condition: Option<AssignCondition>,
+    when: Option<String>,
+}
+
+impl TryFrom<DefinitionDef> for Definition {
+    type Error = String;
+
+    fn try_from(def: DefinitionDef) -> Result<Self, Self::Error> {
+        condition_from_fields("definition", def.condition, def.when).map(Definition)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignCondition {
+    And(AssignConditionComposite),
+    Or(AssignConditionComposite),
+    Not(Box<AssignCondition>),
+    TitleRegex(AssignConditionTitleRegex),
+    InfoRegex(AssignConditionInfoRegex),
+    DialogType(AssignConditionDialogType),
+    ContactPresent(AssignConditionContactPresent),
+    ExternalExecutable(AssignConditionExternalExecutable),
+    // Name of the condition in "definitions" section. All references are
+    // replaced by referenced conditions during rules loading.
+    Ref(String),
+    // Note, for this filters order of filters in rules file is important
+    NotMatched,
+}
+
+/// Regex from rules file. Variables, referenced as ${name}, are substituted
+/// by load_rules, which knows their values. Until then, only regexes valid
+/// without substitution can be matched.
+#[derive(Clone)]
+pub struct RegexPattern {
+    source: String,
+    compiled: Option<regex::Regex>,
+}
+
+// Finds the first possible variable reference in |source|: "${",
+// identifier and "}". Returns its byte range and variable name.
+fn find_variable(source: &str) -> Option<(std::ops::Range<usize>, &str)> {
+    let mut offset = 0;
+    while let Some(start) = source[offset..].find("${").map(|idx| offset + idx) {
+        let after_start = &source[start + 2..];
+        let name_len = after_start
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(after_start.len());
+        let name = &after_start[..name_len];
+        let is_identifier = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+        if is_identifier && after_start[name_len..].starts_with('}') {
+            return Some((start..start + 2 + name_len + 1, name));
+        }
+        offset = start + 2;
+    }
+    None
+}
+
+impl RegexPattern {
+    /// Fails for invalid regex, unless it may become valid after
+    /// substituting variables.
+    pub fn new(source: String) -> Result<Self, regex::Error> {
+        let compiled = match regex::Regex::new(&source) {
+            Ok(compiled) => Some(compiled),
+            Err(_) if find_variable(&source).is_some() => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Self { source, compiled })
+    }
+
+    /// Fails for regex referencing variables, if rules were not loaded by
+    /// load_rules, which substitutes them.
+    pub fn regex(&self) -> Result<&regex::Regex> {
+        self.compiled.as_ref().ok_or_else(|| {
+            eyre!(
+                "Regex {} references variables, rules must be loaded by load_rules",
+                self.source
+            )
+        })
+    }
+
+    pub fn is_match(&self, haystack: &str) -> Result<bool> {
+        Ok(self.regex()?.is_match(haystack))
+    }
+
+    /// Substitutes references to |variables| and compiles the result.
+    /// References to unknown names are kept, as "${name}" may be valid
+    /// regex, e.g. inside character class.
+    fn substitute_variables(
+        &mut self,
+        variables: &collections::BTreeMap<String, String>,
+    ) -> Result<()> {
+        let mut result = String::new();
+        let mut unknown = None;
+        let mut rest = self.source.as_str();
+        while let Some((range, name)) = find_variable(rest) {
+            result.push_str(&rest[..range.start]);
+            match variables.get(name) {
+                Some(value) => result.push_str(value),
+                None => {
+                    unknown.get_or_insert(name);
+                    result.push_str(&rest[range.clone()]);
+                }
+            }
+            rest = &rest[range.end..];
+        }
+        result.push_str(rest);
+        let compiled = regex::Regex::new(&result).map_err(|e| match unknown {
+            Some(name) => eyre!("Unknown variable \"{name}\" in regex {}", self.source),
+            None => eyre!(
+                "Invalid regex {} after substituting variables; {e}",
+                self.source
+            ),
+        })?;
+        self.compiled = Some(compiled);
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for RegexPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let content = String::deserialize(deserializer)?;
+        Self::new(content).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AssignConditionTitleRegex {
+    pub regex_match: RegexPattern,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AssignConditionInfoRegex {
+    pub regex_match: RegexPattern,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AssignConditionContactPresent {
+    pub login: String,
+}
+
+#[derive(Clone, Deserialize, PartialEq)]
+pub enum DialogType {
+    User,
+    Group,
+    Channel,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AssignConditionDialogType {
+    pub dialog_type: DialogType,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AssignConditionComposite {
+    pub children: Vec<AssignCondition>,
+}
+
+/// How external executable gets dialog and reports result.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutableProtocol {
+    // Dialog is described by placeholders in "params", zero exit code
+    // means match.
+    #[default]
+    ExitCode,
+    // Dialog is described by JSON on stdin, result is JSON on stdout.
+    Json,
+}
+
+/// Result of external_executable condition, when executable does not
+/// finish in time.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutAction {
+    // Rules evaluation fails, so no folders are changed.
+    #[default]
+    Error,
+    Match,
+    NoMatch,
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct AssignConditionExternalExecutable {
+    pub path: String,
+    #[serde(default)]
+    pub params: Vec<String>,
+    #[serde(default)]
+    pub protocol: ExecutableProtocol,
+    // Options of json protocol. Usernames of participants and number of
+    // latest messages passed to executable.
+    #[serde(default)]
+    pub include_participants: bool,
+    #[serde(default)]
+    pub include_messages: usize,
+    // Minimal score, returned by executable, for condition to match.
+    pub min_score: Option<f64>,
+    // Keep one process for all dialogs, passing them line by line.
+    #[serde(default)]
+    pub persistent: bool,
+    // Time limit of executable run, or of response in persistent mode,
+    // 60 seconds by default.
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub on_timeout: TimeoutAction,
+    // Variables added to environment of executable, which is emptied
+    // before if "clear_env" is set.
+    #[serde(default)]
+    pub env: collections::BTreeMap<String, String>,
+    #[serde(default)]
+    pub clear_env: bool,
+    pub cwd: Option<String>,
+    // Content of stdin with exit_code protocol, empty by default.
+    pub stdin: Option<String>,
+    #[serde(skip)]
+    pub state: classifier::ClassifierState,
+}
+
+pub fn is_placeholder(param: &str) -> bool {
+    param.starts_with('@') && param.ends_with('@')
+}
+
+pub fn validate_rules(filters: &ChatFilters) -> Result<()> {
+    utils::validate_folder_titles(filters.iter().map(|filter| filter.name.as_str()))
+}
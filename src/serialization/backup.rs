@@ -0,0 +1,87 @@
+use super::{DialogFiltersDef, PortableDialogFilters, PortablePeer};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+
+use grammers_tl_types as tl_types;
+
+// Version history:
+// 1 - raw Telegram structures (see DialogFiltersDef), no envelope;
+// 2 - PortableDialogFilters with "format_version" field, no envelope;
+// 3 - BackupFile envelope with PortableDialogFilters inside.
+pub const CURRENT_FORMAT_VERSION: u32 = 3;
+
+/// Backup file envelope.
+/// Fields describing backup origin are absent in files migrated from older
+/// format versions.
+#[derive(Deserialize, Serialize)]
+pub struct BackupFile {
+    pub format_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_version: Option<String>,
+    // RFC 3339 timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<i64>,
+    pub folders: PortableDialogFilters,
+}
+
+impl BackupFile {
+    pub fn new(account_id: i64, folders: PortableDialogFilters) -> Self {
+        let created_at = time::OffsetDateTime::now_utc()
+            .format(&time::format_description::well_known::Rfc3339)
+            .ok();
+        Self {
+            format_version: CURRENT_FORMAT_VERSION,
+            tool_version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+            created_at,
+            account_id: Some(account_id),
+            folders,
+        }
+    }
+
+    /// Reads backup of any supported format version, migrating it to the
+    /// current one.
+    pub fn from_value(value: serde_json::Value) -> Result<Self> {
+        let format_version = match value.get("format_version") {
+            None => 1,
+            Some(version) => version
+                .as_u64()
+                .ok_or_else(|| eyre!("Invalid backup format version {version}"))?,
+        };
+        match format_version {
+            1 => {
+                let filters = DialogFiltersDef::deserialize(value)?;
+                Ok(migrate_from_v1(&filters))
+            }
+            2 => {
+                let folders = PortableDialogFilters::deserialize(value)?;
+                Ok(Self::migrated(folders))
+            }
+            3 => Ok(Self::deserialize(value)?),
+            _ => Err(eyre!(
+                "Backup format version {format_version} is not supported, \
+                 maximal supported version is {CURRENT_FORMAT_VERSION}"
+            )),
+        }
+    }
+
+    fn migrated(folders: PortableDialogFilters) -> Self {
+        Self {
+            format_version: CURRENT_FORMAT_VERSION,
+            tool_version: None,
+            created_at: None,
+            account_id: None,
+            folders,
+        }
+    }
+}
+
+// Version 1 contains only IDs and access hashes of peers, so restoring
+// such backups is still reliable only on the same account.
+fn migrate_from_v1(filters: &tl_types::types::messages::DialogFilters) -> BackupFile {
+    BackupFile::migrated(PortableDialogFilters::from_dialog_filters(
+        filters,
+        PortablePeer::from_input_peer,
+    ))
+}
@@ -4,6 +4,12 @@ use serde_with::{DeserializeAs, SerializeAs};
 
 use grammers_tl_types as tl_types;
 
+mod backup;
+mod portable;
+
+pub use backup::BackupFile;
+pub use portable::{PeerType, PortableDialogFilters, PortablePeer};
+
 macro_rules! impl_serialize_as {
     ($remote_type: ty, $local_type: ty) => {
         impl SerializeAs<$remote_type> for $local_type {
@@ -163,6 +169,8 @@ enum DialogFilterDef {
 
 impl_serialize_as!(tl_types::enums::DialogFilter, DialogFilterDef);
 
+// Used only for reading backups of format version 1, so fields added to
+// Telegram structures later should be declared with #[serde(default)].
 #[serde_as]
 #[derive(Deserialize, Serialize)]
 #[serde(remote = "tl_types::types::messages::DialogFilters")]
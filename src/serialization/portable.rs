@@ -0,0 +1,314 @@
+use serde::{Deserialize, Serialize};
+
+use grammers_tl_types as tl_types;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerType {
+    // Account which owns the backup ("Saved messages").
+    #[serde(rename = "self")]
+    Myself,
+    User,
+    Chat,
+    Channel,
+}
+
+/// Account-independent description of the peer.
+/// |id| of users and channels is the same for all accounts, but
+/// |access_hash| is valid only for the account, which made the backup.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct PortablePeer {
+    #[serde(rename = "type")]
+    pub peer_type: PeerType,
+    pub id: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_hash: Option<i64>,
+}
+
+impl PortablePeer {
+    /// Builds description containing only information available in |peer|
+    /// itself. Returns None for empty peers.
+    pub fn from_input_peer(peer: &tl_types::enums::InputPeer) -> Option<Self> {
+        let (peer_type, id, access_hash) = match peer {
+            tl_types::enums::InputPeer::Empty => {
+                return None;
+            }
+            tl_types::enums::InputPeer::PeerSelf => (PeerType::Myself, 0, None),
+            tl_types::enums::InputPeer::Chat(chat) => (PeerType::Chat, chat.chat_id, None),
+            tl_types::enums::InputPeer::User(user) => {
+                (PeerType::User, user.user_id, Some(user.access_hash))
+            }
+            tl_types::enums::InputPeer::Channel(channel) => (
+                PeerType::Channel,
+                channel.channel_id,
+                Some(channel.access_hash),
+            ),
+            tl_types::enums::InputPeer::UserFromMessage(user) => {
+                (PeerType::User, user.user_id, None)
+            }
+            tl_types::enums::InputPeer::ChannelFromMessage(channel) => {
+                (PeerType::Channel, channel.channel_id, None)
+            }
+        };
+        Some(Self {
+            peer_type,
+            id,
+            username: None,
+            title: None,
+            access_hash,
+        })
+    }
+
+    /// Builds raw peer using stored |access_hash|. Only reliable on the
+    /// account which made the backup.
+    pub fn to_input_peer(&self) -> Option<tl_types::enums::InputPeer> {
+        match self.peer_type {
+            PeerType::Myself => Some(tl_types::enums::InputPeer::PeerSelf),
+            PeerType::Chat => Some(tl_types::enums::InputPeer::Chat(
+                tl_types::types::InputPeerChat { chat_id: self.id },
+            )),
+            PeerType::User => self.access_hash.map(|access_hash| {
+                tl_types::enums::InputPeer::User(tl_types::types::InputPeerUser {
+                    user_id: self.id,
+                    access_hash,
+                })
+            }),
+            PeerType::Channel => self.access_hash.map(|access_hash| {
+                tl_types::enums::InputPeer::Channel(tl_types::types::InputPeerChannel {
+                    channel_id: self.id,
+                    access_hash,
+                })
+            }),
+        }
+    }
+
+    /// Human-readable peer description for log messages.
+    pub fn display_name(&self) -> String {
+        match (&self.username, &self.title) {
+            (Some(username), _) => format!("@{username}"),
+            (None, Some(title)) => format!("\"{title}\""),
+            (None, None) => format!("{:?} {}", self.peer_type, self.id),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PortableDialogFilter {
+    Filter {
+        title: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        emoticon: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        color: Option<i32>,
+        #[serde(default)]
+        contacts: bool,
+        #[serde(default)]
+        non_contacts: bool,
+        #[serde(default)]
+        groups: bool,
+        #[serde(default)]
+        broadcasts: bool,
+        #[serde(default)]
+        bots: bool,
+        #[serde(default)]
+        exclude_muted: bool,
+        #[serde(default)]
+        exclude_read: bool,
+        #[serde(default)]
+        exclude_archived: bool,
+        #[serde(default)]
+        pinned_peers: Vec<PortablePeer>,
+        #[serde(default)]
+        include_peers: Vec<PortablePeer>,
+        #[serde(default)]
+        exclude_peers: Vec<PortablePeer>,
+    },
+    Chatlist {
+        title: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        emoticon: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        color: Option<i32>,
+        #[serde(default)]
+        has_my_invites: bool,
+        #[serde(default)]
+        pinned_peers: Vec<PortablePeer>,
+        #[serde(default)]
+        include_peers: Vec<PortablePeer>,
+    },
+    Default,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PortableDialogFilters {
+    #[serde(default)]
+    pub tags_enabled: bool,
+    pub filters: Vec<PortableDialogFilter>,
+}
+
+fn describe_peers<F>(peers: &[tl_types::enums::InputPeer], describe: &mut F) -> Vec<PortablePeer>
+where
+    F: FnMut(&tl_types::enums::InputPeer) -> Option<PortablePeer>,
+{
+    peers.iter().filter_map(describe).collect()
+}
+
+fn resolve_peers<F>(peers: &[PortablePeer], resolve: &mut F) -> Vec<tl_types::enums::InputPeer>
+where
+    F: FnMut(&PortablePeer) -> Option<tl_types::enums::InputPeer>,
+{
+    peers.iter().filter_map(resolve).collect()
+}
+
+impl PortableDialogFilters {
+    /// Converts raw Telegram filters, using |describe| to collect
+    /// information about each peer.
+    pub fn from_dialog_filters<F>(
+        filters: &tl_types::types::messages::DialogFilters,
+        mut describe: F,
+    ) -> Self
+    where
+        F: FnMut(&tl_types::enums::InputPeer) -> Option<PortablePeer>,
+    {
+        let portable_filters = filters
+            .filters
+            .iter()
+            .map(|filter| match filter {
+                tl_types::enums::DialogFilter::Filter(f) => PortableDialogFilter::Filter {
+                    title: f.title.clone(),
+                    emoticon: f.emoticon.clone(),
+                    color: f.color,
+                    contacts: f.contacts,
+                    non_contacts: f.non_contacts,
+                    groups: f.groups,
+                    broadcasts: f.broadcasts,
+                    bots: f.bots,
+                    exclude_muted: f.exclude_muted,
+                    exclude_read: f.exclude_read,
+                    exclude_archived: f.exclude_archived,
+                    pinned_peers: describe_peers(&f.pinned_peers, &mut describe),
+                    include_peers: describe_peers(&f.include_peers, &mut describe),
+                    exclude_peers: describe_peers(&f.exclude_peers, &mut describe),
+                },
+                tl_types::enums::DialogFilter::Chatlist(cl) => PortableDialogFilter::Chatlist {
+                    title: cl.title.clone(),
+                    emoticon: cl.emoticon.clone(),
+                    color: cl.color,
+                    has_my_invites: cl.has_my_invites,
+                    pinned_peers: describe_peers(&cl.pinned_peers, &mut describe),
+                    include_peers: describe_peers(&cl.include_peers, &mut describe),
+                },
+                tl_types::enums::DialogFilter::Default => PortableDialogFilter::Default,
+            })
+            .collect();
+        Self {
+            tags_enabled: filters.tags_enabled,
+            filters: portable_filters,
+        }
+    }
+
+    /// Titles of all filters, except the default one.
+    pub fn titles(&self) -> impl Iterator<Item = &str> {
+        self.filters.iter().filter_map(|filter| match filter {
+            PortableDialogFilter::Filter { title, .. }
+            | PortableDialogFilter::Chatlist { title, .. } => Some(title.as_str()),
+            PortableDialogFilter::Default => None,
+        })
+    }
+
+    /// All peers mentioned in filters, possibly with duplicates.
+    pub fn peers(&self) -> impl Iterator<Item = &PortablePeer> {
+        self.filters.iter().flat_map(|filter| {
+            let lists: Vec<&Vec<PortablePeer>> = match filter {
+                PortableDialogFilter::Filter {
+                    pinned_peers,
+                    include_peers,
+                    exclude_peers,
+                    ..
+                } => vec![pinned_peers, include_peers, exclude_peers],
+                PortableDialogFilter::Chatlist {
+                    pinned_peers,
+                    include_peers,
+                    ..
+                } => vec![pinned_peers, include_peers],
+                PortableDialogFilter::Default => Vec::new(),
+            };
+            lists.into_iter().flatten()
+        })
+    }
+
+    /// Converts to raw Telegram filters, using |resolve| to obtain peers
+    /// valid for the current account. Unresolved peers are dropped.
+    /// Filter IDs are left zero.
+    pub fn to_dialog_filters<F>(&self, mut resolve: F) -> tl_types::types::messages::DialogFilters
+    where
+        F: FnMut(&PortablePeer) -> Option<tl_types::enums::InputPeer>,
+    {
+        let filters = self
+            .filters
+            .iter()
+            .map(|filter| match filter {
+                PortableDialogFilter::Filter {
+                    title,
+                    emoticon,
+                    color,
+                    contacts,
+                    non_contacts,
+                    groups,
+                    broadcasts,
+                    bots,
+                    exclude_muted,
+                    exclude_read,
+                    exclude_archived,
+                    pinned_peers,
+                    include_peers,
+                    exclude_peers,
+                } => tl_types::enums::DialogFilter::Filter(tl_types::types::DialogFilter {
+                    contacts: *contacts,
+                    non_contacts: *non_contacts,
+                    groups: *groups,
+                    broadcasts: *broadcasts,
+                    bots: *bots,
+                    exclude_muted: *exclude_muted,
+                    exclude_read: *exclude_read,
+                    exclude_archived: *exclude_archived,
+                    id: 0,
+                    title: title.clone(),
+                    emoticon: emoticon.clone(),
+                    color: *color,
+                    pinned_peers: resolve_peers(pinned_peers, &mut resolve),
+                    include_peers: resolve_peers(include_peers, &mut resolve),
+                    exclude_peers: resolve_peers(exclude_peers, &mut resolve),
+                }),
+                PortableDialogFilter::Chatlist {
+                    title,
+                    emoticon,
+                    color,
+                    has_my_invites,
+                    pinned_peers,
+                    include_peers,
+                } => {
+                    tl_types::enums::DialogFilter::Chatlist(tl_types::types::DialogFilterChatlist {
+                        has_my_invites: *has_my_invites,
+                        id: 0,
+                        title: title.clone(),
+                        emoticon: emoticon.clone(),
+                        color: *color,
+                        pinned_peers: resolve_peers(pinned_peers, &mut resolve),
+                        include_peers: resolve_peers(include_peers, &mut resolve),
+                    })
+                }
+                PortableDialogFilter::Default => tl_types::enums::DialogFilter::Default,
+            })
+            .collect();
+        tl_types::types::messages::DialogFilters {
+            tags_enabled: self.tags_enabled,
+            filters,
+        }
+    }
+}
@@ -0,0 +1,321 @@
+//! Storage of Telegram session in file, optionally encrypted by passphrase.
+//! Encrypted file consists of header (magic, PBKDF2 rounds, salt, nonce)
+//! and session encrypted by AES-256-GCM, authenticating the header as well.
+//! Key is derived from passphrase by PBKDF2-HMAC-SHA256.
+//! Session is preceded by credentials of API application it was made
+//! with (magic, ID, hash length and hash), absent in files of old versions.
+
+use crate::api_credentials::ApiCredentials;
+use crate::failure;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit};
+use eyre::{eyre, Result};
+use grammers_client::session::Session;
+use sha2::Sha256;
+use std::env;
+use std::fs;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path;
+
+const MAGIC: &[u8; 8] = b"TGTSENC2";
+// Prefix of magic of all versions of encrypted files.
+const MAGIC_PREFIX: &[u8; 7] = b"TGTSENC";
+const PBKDF2_ROUNDS: u32 = 600_000;
+// Rounds are read before the header is authenticated, so damaged or
+// crafted file must not make key derivation take hours.
+const MAX_PBKDF2_ROUNDS: u32 = 10 * PBKDF2_ROUNDS;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + 4 + SALT_LEN + NONCE_LEN;
+const API_MAGIC: &[u8; 8] = b"TGTSAPI1";
+// Passphrase used when key file is not given.
+const PASSPHRASE_ENV: &str = "TG_TOOL_SESSION_PASSPHRASE";
+
+/// Options defining where passphrase of encrypted session comes from: key
+/// file, environment variable or interactive prompt.
+#[derive(Clone, Default)]
+pub struct SessionKeyParams {
+    pub key_file: Option<path::PathBuf>,
+}
+
+impl SessionKeyParams {
+    /// Returns passphrase, asking it twice if |confirm| is set and it is
+    /// entered interactively.
+    fn passphrase(&self, confirm: bool) -> Result<Vec<u8>> {
+        if let Some(key_file) = &self.key_file {
+            let mut key = fs::read(key_file).map_err(|e| {
+                failure::validation(eyre!("Failed to read {}: {e}", key_file.display()))
+            })?;
+            // Key files are often written by editors or echo.
+            while key.last().is_some_and(|b| b.is_ascii_whitespace()) {
+                key.pop();
+            }
+            return Ok(key);
+        }
+        if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+            return Ok(passphrase.into_bytes());
+        }
+        let passphrase = rpassword::prompt_password("Enter session passphrase: ")?;
+        if confirm && rpassword::prompt_password("Repeat session passphrase: ")? != passphrase {
+            return Err(failure::validation(eyre!("Passphrases do not match")));
+        }
+        Ok(passphrase.into_bytes())
+    }
+}
+
+fn cipher(passphrase: &[u8], salt: &[u8], rounds: u32) -> Aes256Gcm {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase, salt, rounds, &mut key);
+    Aes256Gcm::new(&key.into())
+}
+
+fn encrypt_with_rounds(plain: &[u8], passphrase: &[u8], rounds: u32) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    getrandom::getrandom(&mut salt).map_err(|e| eyre!("Failed to generate salt: {e}"))?;
+    getrandom::getrandom(&mut nonce).map_err(|e| eyre!("Failed to generate nonce: {e}"))?;
+    let mut result = Vec::with_capacity(HEADER_LEN + plain.len() + TAG_LEN);
+    result.extend_from_slice(MAGIC);
+    result.extend_from_slice(&rounds.to_le_bytes());
+    result.extend_from_slice(&salt);
+    result.extend_from_slice(&nonce);
+    let encrypted = cipher(passphrase, &salt, rounds)
+        .encrypt(
+            &nonce.into(),
+            Payload {
+                msg: plain,
+                aad: &result,
+            },
+        )
+        .map_err(|_| eyre!("Failed to encrypt session"))?;
+    result.extend_from_slice(&encrypted);
+    Ok(result)
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC_PREFIX)
+}
+
+/// Checks header of encrypted |data| before asking passphrase, returning
+/// number of PBKDF2 rounds.
+fn check_header(data: &[u8]) -> Result<u32> {
+    if !data.starts_with(MAGIC) {
+        return Err(failure::auth(eyre!(
+            "Session file is encrypted in unsupported format, log in again"
+        )));
+    }
+    if data.len() < HEADER_LEN + TAG_LEN {
+        return Err(failure::auth(eyre!("Encrypted session file is truncated")));
+    }
+    let rounds = u32::from_le_bytes(data[MAGIC.len()..MAGIC.len() + 4].try_into()?);
+    if !(1..=MAX_PBKDF2_ROUNDS).contains(&rounds) {
+        return Err(failure::auth(eyre!(
+            "Encrypted session file is damaged: invalid number of PBKDF2 rounds {rounds}"
+        )));
+    }
+    Ok(rounds)
+}
+
+fn decrypt(data: &[u8], passphrase: &[u8]) -> Result<Vec<u8>> {
+    let rounds = check_header(data)?;
+    let (header, encrypted) = data.split_at(HEADER_LEN);
+    let salt = &header[MAGIC.len() + 4..MAGIC.len() + 4 + SALT_LEN];
+    let nonce: [u8; NONCE_LEN] = header[HEADER_LEN - NONCE_LEN..].try_into()?;
+    cipher(passphrase, salt, rounds)
+        .decrypt(
+            &nonce.into(),
+            Payload {
+                msg: encrypted,
+                aad: header,
+            },
+        )
+        .map_err(|_| {
+            failure::auth(eyre!(
+                "Failed to decrypt session file: wrong passphrase or damaged file"
+            ))
+        })
+}
+
+fn encode_payload(session: &[u8], api: &ApiCredentials) -> Result<Vec<u8>> {
+    let hash_len = u8::try_from(api.hash.len()).map_err(|_| eyre!("API hash is too long"))?;
+    let mut payload = Vec::with_capacity(API_MAGIC.len() + 5 + api.hash.len() + session.len());
+    payload.extend_from_slice(API_MAGIC);
+    payload.extend_from_slice(&api.id.to_le_bytes());
+    payload.push(hash_len);
+    payload.extend_from_slice(api.hash.as_bytes());
+    payload.extend_from_slice(session);
+    Ok(payload)
+}
+
+/// Splits |payload| into API credentials, if they are stored, and session.
+fn decode_payload(payload: &[u8]) -> Result<(Option<ApiCredentials>, &[u8])> {
+    let Some(rest) = payload.strip_prefix(API_MAGIC) else {
+        return Ok((None, payload));
+    };
+    let truncated = || eyre!("Session file is truncated");
+    let id = i32::from_le_bytes(rest.get(..4).ok_or_else(truncated)?.try_into()?);
+    let hash_len = *rest.get(4).ok_or_else(truncated)? as usize;
+    let hash = rest.get(5..5 + hash_len).ok_or_else(truncated)?;
+    let api = ApiCredentials {
+        id,
+        hash: String::from_utf8(hash.to_vec())?,
+    };
+    Ok((Some(api), &rest[5 + hash_len..]))
+}
+
+/// Session with credentials of API application it was made with.
+pub struct StoredSession {
+    pub session: Session,
+    // None for session files written by old versions.
+    pub api: Option<ApiCredentials>,
+}
+
+/// Loads session from |session_file|, decrypting it if it is encrypted.
+pub fn load_session(
+    session_file: &path::Path,
+    key_params: &SessionKeyParams,
+) -> Result<StoredSession> {
+    let load_error = |e| failure::auth(eyre!("Failed to load session file: {e}"));
+    let data = fs::read(session_file).map_err(|e| load_error(e.to_string()))?;
+    let data = if is_encrypted(&data) {
+        check_header(&data)?;
+        decrypt(&data, &key_params.passphrase(false)?)?
+    } else {
+        data
+    };
+    let (api, session) = decode_payload(&data).map_err(|e| load_error(e.to_string()))?;
+    Ok(StoredSession {
+        session: Session::load(session).map_err(|e| load_error(e.to_string()))?,
+        api,
+    })
+}
+
+/// Returns whether |session_file| holds session of logged in user. Encrypted
+/// session is assumed to hold it, as checking requires passphrase, while
+/// missing or damaged file does not.
+pub fn is_signed_in(session_file: &path::Path) -> bool {
+    let Ok(data) = fs::read(session_file) else {
+        return false;
+    };
+    if is_encrypted(&data) {
+        return true;
+    }
+    decode_payload(&data)
+        .ok()
+        .and_then(|(_, session)| Session::load(session).ok())
+        .is_some_and(|session| session.signed_in())
+}
+
+/// Writes |data| to |file_path| atomically, by writing temporary file in
+/// the same directory and renaming it, so interrupted write does not
+/// damage existing file.
+fn write_atomically(file_path: &path::Path, data: &[u8]) -> Result<()> {
+    let file_name = file_path
+        .file_name()
+        .ok_or_else(|| eyre!("Invalid session file path {}", file_path.display()))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".tmp-{}", std::process::id()));
+    let temp_path = file_path.with_file_name(temp_name);
+    let write = || -> Result<()> {
+        let mut file = fs::File::create(&temp_path)?;
+        #[cfg(unix)]
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, file_path)?;
+        Ok(())
+    };
+    write().map_err(|e| {
+        let _ = fs::remove_file(&temp_path);
+        eyre!("Failed to write session file {}: {e}", file_path.display())
+    })
+}
+
+/// Saves |session| made with |api| application to |session_file|, readable
+/// only by the current user. Session is encrypted if |key_params| is given.
+pub fn save_session(
+    session_file: &path::Path,
+    session: &Session,
+    api: &ApiCredentials,
+    key_params: Option<&SessionKeyParams>,
+) -> Result<()> {
+    let payload = encode_payload(&session.save(), api)?;
+    let data = match key_params {
+        Some(key_params) => {
+            encrypt_with_rounds(&payload, &key_params.passphrase(true)?, PBKDF2_ROUNDS)?
+        }
+        None => payload,
+    };
+    write_atomically(session_file, &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_is_decrypted_only_by_right_passphrase() {
+        let plain = b"session bytes, longer than one AES block".to_vec();
+        let encrypted = encrypt_with_rounds(&plain, b"secret", 1000).unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.windows(7).any(|w| w == b"session"));
+        assert_eq!(decrypt(&encrypted, b"secret").unwrap(), plain);
+        assert!(decrypt(&encrypted, b"Secret").is_err());
+        let mut damaged = encrypted.clone();
+        damaged[HEADER_LEN] ^= 1;
+        assert!(decrypt(&damaged, b"secret").is_err());
+        assert!(decrypt(&encrypted[..HEADER_LEN], b"secret").is_err());
+        // Header is authenticated, and rounds are checked before use.
+        let mut tampered = encrypted.clone();
+        tampered[HEADER_LEN - 1] ^= 1;
+        assert!(decrypt(&tampered, b"secret").is_err());
+        tampered[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = decrypt(&tampered, b"secret").unwrap_err();
+        assert!(error.to_string().contains("rounds"));
+    }
+
+    #[test]
+    fn api_credentials_are_stored_before_session() {
+        let api = ApiCredentials {
+            id: 12345,
+            hash: "0123456789abcdef".to_owned(),
+        };
+        let payload = encode_payload(b"session", &api).unwrap();
+        let (stored, session) = decode_payload(&payload).unwrap();
+        assert_eq!(stored, Some(api));
+        assert_eq!(session, b"session");
+        // Session files of old versions have no credentials.
+        assert_eq!(decode_payload(b"session").unwrap(), (None, &b"session"[..]));
+        assert!(decode_payload(&payload[..12]).is_err());
+    }
+
+    #[test]
+    fn session_file_is_replaced_atomically() {
+        let dir = env::temp_dir().join(format!("tg-tool-session-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let session_file = dir.join("my.session");
+        fs::write(&session_file, b"damaged").unwrap();
+        assert!(!is_signed_in(&session_file));
+        let api = ApiCredentials {
+            id: 1,
+            hash: "x".to_owned(),
+        };
+        let session = Session::new();
+        session.set_user(42, 2, false);
+        save_session(&session_file, &session, &api, None).unwrap();
+        assert!(is_signed_in(&session_file));
+        // Only the session file is left, without temporary one.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(&session_file).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!is_signed_in(&session_file));
+    }
+}
@@ -1,17 +1,33 @@
-use eyre::Result;
+use crate::backend::TelegramBackend;
+use eyre::{eyre, Result};
 use grammers_tl_types as tl_types;
 use log::{error, warn};
+use serde::Serialize;
 
 const PEER_COUNT_FREE_LIMIT: usize = 100;
 const PEER_COUNT_PREMIUM_LIMIT: usize = 200;
+// Found experimentally. Like Telegram clients, counted in UTF-16 code units,
+// so e.g. most emoji take 2 units.
+pub const FOLDER_TITLE_LEN_LIMIT: usize = 12;
 
-pub async fn get_dialog_filters(
-    tg_client: &grammers_client::Client,
-) -> Result<tl_types::types::messages::DialogFilters> {
-    let request = tl_types::functions::messages::GetDialogFilters {};
-    let tl_types::enums::messages::DialogFilters::Filters(filters) =
-        tg_client.invoke(&request).await?;
-    Ok(filters)
+pub fn folder_title_len(title: &str) -> usize {
+    title.encode_utf16().count()
+}
+
+/// Checks that all |titles| fit Telegram limit for folder title length.
+pub fn validate_folder_titles<'a>(titles: impl IntoIterator<Item = &'a str>) -> Result<()> {
+    let wrong_titles: Vec<String> = titles
+        .into_iter()
+        .filter(|title| folder_title_len(title) > FOLDER_TITLE_LEN_LIMIT)
+        .map(|title| format!("\"{title}\" (length {})", folder_title_len(title)))
+        .collect();
+    if !wrong_titles.is_empty() {
+        let joined = wrong_titles.join(", ");
+        return Err(eyre!(
+            "Folder names {joined} exceed Telegram limit of {FOLDER_TITLE_LEN_LIMIT} UTF-16 code units"
+        ));
+    }
+    Ok(())
 }
 
 pub fn get_filter_id(filter: &tl_types::enums::DialogFilter) -> Option<i32> {
@@ -34,7 +50,7 @@ pub fn set_filter_id(filter: &mut tl_types::enums::DialogFilter, id: i32) {
     }
 }
 
-fn get_filter_title(filter: &tl_types::enums::DialogFilter) -> Option<&str> {
+pub fn get_filter_title(filter: &tl_types::enums::DialogFilter) -> Option<&str> {
     match filter {
         tl_types::enums::DialogFilter::Filter(filter) => Some(&filter.title),
         tl_types::enums::DialogFilter::Default => None,
@@ -163,19 +179,51 @@ fn warn_if_neccessary(saved_filter: &tl_types::enums::DialogFilter) {
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct FolderFailure {
+    pub folder: String,
+    // Original error, so callers can tell e.g. network failure from
+    // rejected folder. Serialized as its message.
+    #[serde(serialize_with = "serialize_error")]
+    pub error: eyre::Report,
+}
+
+fn serialize_error<S: serde::Serializer>(
+    error: &eyre::Report,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(error)
+}
+
+/// Result of apply_dialog_filters. Failure to update one folder does not
+/// stop processing of others.
+#[derive(Debug, Default, Serialize)]
+pub struct ApplyReport {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub failed: Vec<FolderFailure>,
+}
+
 /// Updates filters in the Telegram  based on desired_filters.
 /// If some filters with same names already present, attempts merge their
 /// contents.
 /// During merging uses filter |title| field.
 /// Filter |id| field in |desired_filters| is ignored.
-pub async fn apply_dialog_filters(
-    tg_client: &grammers_client::Client,
+/// Returns error if nothing was applied: current filters are not loaded, or
+/// no filter is created or updated. In the latter case error of the first
+/// failed filter is returned, keeping its kind.
+pub async fn apply_dialog_filters<B: TelegramBackend>(
+    backend: &B,
     desired_filters: &tl_types::types::messages::DialogFilters,
-) -> Result<()> {
-    let current_filters = get_dialog_filters(tg_client).await?;
+) -> Result<ApplyReport> {
+    validate_folder_titles(desired_filters.filters.iter().filter_map(get_filter_title))?;
+    let current_filters = backend.get_dialog_filters().await?;
     let mut next_available_filter_id = find_next_available_filter_id(&current_filters.filters);
-    let mut maybe_error = None;
+    let mut report = ApplyReport::default();
     for saved_filter in &desired_filters.filters {
+        let title = get_filter_title(saved_filter)
+            .unwrap_or_default()
+            .to_owned();
         if let Some(current_filter) = find_matching_filter(&current_filters.filters, saved_filter) {
             // Matching filter found in current Telegram state,
             // update it rather then creating new.
@@ -190,36 +238,62 @@ pub async fn apply_dialog_filters(
             let mut merged_filter = merge_filters(current_filter, saved_filter);
             set_filter_id(&mut merged_filter, current_filter_id);
             warn_if_neccessary(&merged_filter);
-            let update_request = tl_types::functions::messages::UpdateDialogFilter {
-                id: current_filter_id,
-                filter: Some(merged_filter),
-            };
-            match tg_client.invoke(&update_request).await {
-                Ok(_) => {}
-                Err(error) => {
-                    error!("Error updating filter {update_request:?}");
-                    maybe_error = Some(error);
-                }
+            if let Err(error) = backend
+                .update_dialog_filter(current_filter_id, Some(merged_filter.clone()))
+                .await
+            {
+                error!("Error updating filter {current_filter_id} {merged_filter:?}");
+                report.failed.push(FolderFailure {
+                    folder: title,
+                    error,
+                });
+            } else {
+                report.updated.push(title);
             }
         } else {
             let new_filter_id = next_available_filter_id;
             next_available_filter_id += 1;
-            let create_request = tl_types::functions::messages::UpdateDialogFilter {
-                id: new_filter_id,
-                filter: Some(saved_filter.clone()),
-            };
             warn_if_neccessary(saved_filter);
-            match tg_client.invoke(&create_request).await {
-                Ok(_) => {}
-                Err(error) => {
-                    error!("Error creating filter {create_request:?}");
-                    maybe_error = Some(error);
-                }
+            if let Err(error) = backend
+                .update_dialog_filter(new_filter_id, Some(saved_filter.clone()))
+                .await
+            {
+                error!("Error creating filter {new_filter_id} {saved_filter:?}");
+                report.failed.push(FolderFailure {
+                    folder: title,
+                    error,
+                });
+            } else {
+                report.created.push(title);
             }
         }
     }
-    match maybe_error {
-        None => Ok(()),
-        Some(e) => Err(e.into()),
+    if report.created.is_empty() && report.updated.is_empty() && !report.failed.is_empty() {
+        let folders: Vec<String> = report.failed.iter().map(|f| f.folder.clone()).collect();
+        let first = report.failed.swap_remove(0);
+        return Err(first.error.wrap_err(format!(
+            "Failed to update any of folders {}",
+            folders.join(", ")
+        )));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_title_len_counts_utf16_code_units() {
+        // Cyrillic letters take one unit, most emoji take two.
+        assert_eq!(folder_title_len("Важные чаты!"), 12);
+        assert_eq!(folder_title_len("📚📚📚📚📚📚"), 12);
+        assert_eq!(folder_title_len("Работа 💼💼💼"), 13);
+        assert!(validate_folder_titles(["Важные чаты!", "📚📚📚📚📚📚"]).is_ok());
+        let error = validate_folder_titles(["Работа 💼💼💼", "Ok"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Folder names \"Работа 💼💼💼\" (length 13) exceed Telegram limit of 12 UTF-16 code units"
+        );
     }
 }